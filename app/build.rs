@@ -10,6 +10,12 @@ fn main() {
     for f in files {
         let name = f.file_name();
         let name = name.to_string_lossy().to_owned();
+
+        // Animation markers and import settings are read while preprocessing the model they
+        // belong to
+        if name.ends_with(".markers.json") || name.ends_with(".import.json") {
+            continue;
+        }
         let (raw_name, extension) = {
             let mut a = name.split('.');
             (a.next().unwrap(), a.last().unwrap_or(""))
@@ -20,8 +26,10 @@ fn main() {
 
         let (out, new_ext) = match extension {
             "gltf" | "glb" => {
-                let bundle = preprocessor::model::load_gltf(f.path());
-                let bytes = rmp_serde::to_vec(&bundle).unwrap();
+                let settings = preprocessor::settings::ImportSettings::load_for(f.path());
+                let bundle = preprocessor::model::load_gltf(f.path(), &settings);
+                let bytes = core3d::container::write_model(&bundle)
+                    .unwrap_or_else(|e| panic!("Failed to write \"{name}\": {e}"));
                 (bytes, "model")
             }
             "jpg" | "jpeg" | "png" => {
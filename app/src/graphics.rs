@@ -1,42 +1,149 @@
 use citro3d::{
     attrib,
+    buffer::{self, Buffer},
     macros::include_shader,
     math::{self, ClipPlanes, Projection},
+    texenv::{self, CombineFunc, Mode, Source, TexEnv},
 };
+use core3d::{
+    container::VertexData,
+    layout::ComponentType,
+    material::{AlphaMode, Material},
+    morph::{self, MorphTarget},
+    packed::{Dequantize, VertexFormat},
+    ray::Ray,
+    Vertex,
+};
+use ctru::linear::LinearAllocator;
+use glam::{Mat4, Vec2};
 
 pub const VERTEX_SHADER: &[u8] = include_shader!("../shaders/vshader.pica");
 
-pub fn attr_info() -> attrib::Info {
-    use attrib::Register;
+/// Attribute layout for vertices in `format`, built from the description of the vertex type in
+/// `core3d` so it always matches
+pub fn attr_info(format: VertexFormat) -> attrib::Info {
     // Configure attributes for use with the vertex shader
     let mut attr_info = attrib::Info::new();
 
-    // Pos
-    attr_info
-        .add_loader(Register::V0, attrib::Format::Float, 3)
-        .unwrap();
+    for attribute in format.attributes() {
+        let register = attrib::Register::new(attribute.register.into()).unwrap();
+        let component = match attribute.component {
+            ComponentType::Byte => attrib::Format::Byte,
+            ComponentType::UnsignedByte => attrib::Format::UnsignedByte,
+            ComponentType::Short => attrib::Format::Short,
+            ComponentType::Float => attrib::Format::Float,
+        };
+        attr_info
+            .add_loader(register, component, attribute.count)
+            .unwrap();
+    }
 
-    // Norm
     attr_info
-        .add_loader(Register::V1, attrib::Format::Float, 3)
-        .unwrap();
+}
 
-    // TC
-    attr_info
-        .add_loader(Register::V2, attrib::Format::Float, 2)
-        .unwrap();
+/// Adds a mesh's vertices to `info`, in whichever format they're stored in
+pub fn add_vertices(info: &mut buffer::Info, verts: VertexData, attr_info: &attrib::Info) {
+    match verts {
+        VertexData::Full(verts) => info.add(Buffer::new(verts), attr_info.permutation()),
+        VertexData::Packed(verts, _) => info.add(Buffer::new(verts), attr_info.permutation()),
+    }
+    .unwrap();
+}
 
-    // Joints
-    attr_info
-        .add_loader(Register::V3, attrib::Format::UnsignedByte, 3)
-        .unwrap();
+/// A copy of a mesh's vertices in linear memory, with its morph targets blended in on the CPU
+/// because the vertex shader has no room for them
+pub struct MorphedVertices {
+    base: Vec<Vertex>,
+    pub verts: Vec<Vertex, LinearAllocator>,
+}
 
-    // Weights
-    attr_info
-        .add_loader(Register::V4, attrib::Format::Float, 3)
-        .unwrap();
+impl MorphedVertices {
+    pub fn new(verts: VertexData) -> Self {
+        let base = verts.to_vertices();
+        let mut out = Vec::with_capacity_in(base.len(), LinearAllocator);
+        out.extend_from_slice(&base);
+        Self { base, verts: out }
+    }
 
-    attr_info
+    /// Blends the targets in by `weights` and flushes the result so the GPU sees it
+    pub fn update(&mut self, targets: &[MorphTarget], weights: &[f32]) {
+        morph::blend(&self.base, targets, weights, &mut self.verts);
+        unsafe {
+            ctru_sys::GSPGPU_FlushDataCache(
+                self.verts.as_ptr().cast(),
+                size_of_val(self.verts.as_slice()) as u32,
+            );
+        }
+    }
+}
+
+/// Joint matrices that also turn a mesh's packed positions back into model space
+pub fn dequantize_joints(joints: &[Mat4], dequantize: &Dequantize) -> Vec<Mat4> {
+    let pos_matrix = dequantize.pos_matrix();
+    joints.iter().map(|joint| *joint * pos_matrix).collect()
+}
+
+/// Texture combiner stages for `material`: its texture (if it has one) multiplied by the base
+/// colour, then the emissive colour added on. The constant colours are set by
+/// [`set_material_state`].
+pub fn material_texenvs(material: &Material) -> [TexEnv; 2] {
+    let base = match material.texture {
+        Some(_) => TexEnv::new()
+            .src(Mode::BOTH, Source::Texture0, Some(Source::Constant), None)
+            .func(Mode::BOTH, CombineFunc::Modulate),
+        None => TexEnv::new().src(Mode::BOTH, Source::Constant, None, None),
+    };
+    let emissive = TexEnv::new()
+        .src(Mode::RGB, Source::Previous, Some(Source::Constant), None)
+        .func(Mode::RGB, CombineFunc::Add)
+        .src(Mode::ALPHA, Source::Previous, None, None);
+    [base, emissive]
+}
+
+/// Sets the constant colours for the stages from [`material_texenvs`], which must already be
+/// bound, along with the material's alpha test, blending and culling
+pub fn set_material_state(material: &Material) {
+    unsafe {
+        citro3d_sys::C3D_TexEnvColor(citro3d_sys::C3D_GetTexEnv(0), material.base_color_rgba8());
+        citro3d_sys::C3D_TexEnvColor(citro3d_sys::C3D_GetTexEnv(1), material.emissive_rgba8());
+
+        match material.alpha_test() {
+            Some(cutoff) => citro3d_sys::C3D_AlphaTest(true, ctru_sys::GPU_GEQUAL, cutoff.into()),
+            None => citro3d_sys::C3D_AlphaTest(false, ctru_sys::GPU_ALWAYS, 0),
+        }
+
+        let (src, dst) = match material.alpha_mode {
+            AlphaMode::Blend => (ctru_sys::GPU_SRC_ALPHA, ctru_sys::GPU_ONE_MINUS_SRC_ALPHA),
+            AlphaMode::Opaque | AlphaMode::Mask => (ctru_sys::GPU_ONE, ctru_sys::GPU_ZERO),
+        };
+        citro3d_sys::C3D_AlphaBlend(
+            ctru_sys::GPU_BLEND_ADD,
+            ctru_sys::GPU_BLEND_ADD,
+            src,
+            dst,
+            src,
+            dst,
+        );
+
+        citro3d_sys::C3D_CullFace(if material.double_sided {
+            ctru_sys::GPU_CULL_NONE
+        } else {
+            ctru_sys::GPU_CULL_BACK_CCW
+        });
+    }
+}
+
+/// The ray through a point touched on the bottom screen, treating it as if it were the top
+/// screen. `matrix` uses one of citro3d's tilted projections like [`screen_proj`], which turn
+/// everything a quarter turn because the screens are mounted sideways, so clip space `x` goes
+/// from the bottom of the screen to the top and `y` from right to left.
+pub fn touch_ray((x, y): (u16, u16), matrix: &Mat4) -> Ray {
+    const BOTTOM_SCREEN: Vec2 = Vec2::new(320.0, 240.0);
+    let ndc = Vec2::new(
+        1.0 - y as f32 / BOTTOM_SCREEN.y * 2.0,
+        1.0 - x as f32 / BOTTOM_SCREEN.x * 2.0,
+    );
+    Ray::from_ndc(ndc, matrix)
 }
 
 pub fn screen_proj() -> math::Matrix4 {
@@ -1,20 +1,31 @@
 #![feature(allocator_api)]
 
 use citro3d::{
-    buffer::{self, Buffer, Primitive},
+    buffer::{self, Primitive},
     math,
     render::{ClearFlags, Target},
     shader::{self},
-    texenv,
     texture::{self, Face},
 };
-use core3d::Model;
+use core3d::{
+    bounds::Frustum,
+    container::{self, IndexData, ModelView, VertexData},
+    material::AlphaMode,
+    mixer::AnimationMixer,
+    optimize::{triangle_list, Topology},
+    packed::VertexFormat,
+    player::{AnimationPlayer, PlaybackMode},
+    ray::Hit,
+    scene::MeshInstance,
+};
 use ctru::{
     linear::LinearAllocator,
     prelude::*,
     services::gfx::{RawFrameBuffer, Screen},
 };
-use graphics::{screen_proj, VERTEX_SHADER};
+use glam::{Mat4, Vec4};
+use graphics::{screen_proj, MorphedVertices, VERTEX_SHADER};
+use util::LinearBytes;
 
 pub mod app;
 pub mod graphics;
@@ -74,76 +85,95 @@ fn main() {
 
     let start_time = unsafe { ctru_sys::osGetTime() };
 
-    let attr_info = graphics::attr_info();
-
     println!("Loading assets...");
 
-    // Load exported model
-    let model: Model = {
-        let model_bytes =
-            std::fs::read("romfs:/Bash_3DS.model").expect("Couldn't load model from romfs");
-        rmp_serde::from_slice(&model_bytes).expect("Failed to deserialize model")
-    };
+    // Load exported model into linear memory, so the GPU can use its vertices, indices and
+    // textures in place
+    let mut model_bytes =
+        LinearBytes::read("romfs:/Bash_3DS.model").expect("Couldn't load model from romfs");
+    if container::read_version(&model_bytes) != Ok(container::FORMAT_VERSION) {
+        println!("Converting model from an older format, rebuild the assets to skip this");
+        let model = container::read_model(&model_bytes)
+            .unwrap_or_else(|e| panic!("Failed to load model: {e}"));
+        let bytes = container::write_model(&model).expect("Failed to convert model");
+        model_bytes = LinearBytes::from_slice(&bytes);
+    }
+    let model =
+        ModelView::parse(&model_bytes).unwrap_or_else(|e| panic!("Failed to load model: {e}"));
 
     let end_time = unsafe { ctru_sys::osGetTime() };
     let setup_time = end_time - start_time;
-    println!("Took {setup_time}ms to deserialise model bundle.");
-
-    let mut model1_info = buffer::Info::new();
-    model1_info
-        .add(Buffer::new(&model.meshes[0].verts), attr_info.permutation())
-        .unwrap();
-    let mut model1_inds = Vec::new_in(LinearAllocator);
-    model1_inds.extend_from_slice(&model.meshes[0].inds);
-
-    let mut model2_info = buffer::Info::new();
-    model2_info
-        .add(Buffer::new(&model.meshes[1].verts), attr_info.permutation())
-        .unwrap();
-    let mut model2_inds = Vec::new_in(LinearAllocator);
-    model2_inds.extend_from_slice(&model.meshes[1].inds);
-
-    let tex_ind = 0;
-
-    // Create texture
-    let mut texture1 = texture::Texture::new(texture::TextureParameters::new_2d(
-        model.textures[tex_ind].width,
-        model.textures[tex_ind].height,
-        texture::ColorFormat::Rgba8,
-    ))
-    .unwrap();
-    let mut tex_bytes = Vec::with_capacity_in(model.textures[tex_ind].data.len(), LinearAllocator);
-    tex_bytes.extend_from_slice(&model.textures[tex_ind].data);
-    texture1
-        .load_image(&tex_bytes, Face::default())
-        .expect("Failed to load texture bytes");
-
-    // Create texture
-    let mut texture2 = texture::Texture::new(texture::TextureParameters::new_2d(
-        model.textures[1].width,
-        model.textures[1].height,
-        texture::ColorFormat::Rgba8,
-    ))
-    .unwrap();
-    let mut tex_bytes = Vec::with_capacity_in(model.textures[1].data.len(), LinearAllocator);
-    tex_bytes.extend_from_slice(&model.textures[1].data);
-    texture2
-        .load_image(&tex_bytes, Face::default())
-        .expect("Failed to load texture bytes");
+    println!("Took {setup_time}ms to load model bundle.");
+
+    // Meshes with morph targets are drawn from a copy of their vertices that's updated each frame
+    let mut morphed: Vec<Option<MorphedVertices>> = model
+        .meshes
+        .iter()
+        .map(|mesh| (!mesh.morph_targets.is_empty()).then(|| MorphedVertices::new(mesh.verts)))
+        .collect();
+
+    let meshes: Vec<_> = model
+        .meshes
+        .iter()
+        .enumerate()
+        .map(|(index, mesh)| {
+            if morphed[index].is_some() {
+                return (mesh, graphics::attr_info(VertexFormat::Full), None);
+            }
+            let attr_info = graphics::attr_info(mesh.verts.format());
+            let mut info = buffer::Info::new();
+            graphics::add_vertices(&mut info, mesh.verts, &attr_info);
+            (mesh, attr_info, Some(info))
+        })
+        .collect();
+
+    // Each mesh is drawn once for every node of the scene using it. Blended meshes are drawn
+    // last, so what's behind them is already there.
+    let mut instances = model.scene.mesh_instances(model.meshes.len());
+    instances.sort_by_key(|instance| {
+        let mesh = &model.meshes[instance.mesh];
+        model.materials[mesh.material as usize].alpha_mode == AlphaMode::Blend
+    });
+
+    // Create textures
+    let textures: Vec<_> = model
+        .textures
+        .iter()
+        .map(|tex| {
+            let mut texture = texture::Texture::new(texture::TextureParameters::new_2d(
+                tex.width,
+                tex.height,
+                texture::ColorFormat::Rgba8,
+            ))
+            .unwrap();
+            texture
+                .load_image(tex.data, Face::default())
+                .expect("Failed to load texture bytes");
+            texture
+        })
+        .collect();
 
     // Projection and uniform
     let screen_proj = screen_proj();
 
     let uniform_proj = program.get_uniform("projection").unwrap();
     let uniform_joint = program.get_uniform("jointTransforms").unwrap();
-
-    let textured_stage =
-        texenv::TexEnv::new().src(texenv::Mode::BOTH, texenv::Source::Texture0, None, None);
+    let uniform_uv = program.get_uniform("uvTransform").unwrap();
+    let uniform_weight_scale = program.get_uniform("weightScale").unwrap();
 
     let mut total_frame_time: u64 = 0;
 
     let mut t: f32 = 0.0;
 
+    let animation_player = |index: usize| {
+        let mut player = AnimationPlayer::new(index, &model.animations[index], PlaybackMode::Loop);
+        player.speed = 0.25;
+        player
+    };
+
+    let mut mixer = AnimationMixer::new();
+    mixer.play(animation_player(0));
+
     println!("\x1b[29;16HPress Start to exit");
 
     while apt.main_loop() {
@@ -154,9 +184,51 @@ fn main() {
         model_matrix.rotate_y((4.0 * t).to_radians());
         model_matrix.translate(0.0, -1.0, -4.5);
         let mvp = screen_proj * model_matrix;
+        // In model space, to test the meshes' bounds against
+        let frustum = Frustum::from_matrix(&Mat4::from(mvp));
 
-        let animated_pose = model.animations[0].sample(t * 0.25);
+        mixer.update(0.16);
+        let animated_pose = mixer.sample(&model.animations, &model.skeleton);
         let joint_transforms = model.skeleton.apply_pose_to_joints(&animated_pose).unwrap();
+        // Nodes that are joints follow the animated skeleton
+        let skeleton_transforms = model
+            .skeleton
+            .model_space_transforms(&animated_pose)
+            .unwrap();
+        let node_transforms = model
+            .scene
+            .model_space_transforms(Some(&skeleton_transforms));
+        let instance_joints = |instance: MeshInstance| {
+            let mesh = &model.meshes[instance.mesh];
+            model.scene.instance_joints(
+                instance,
+                &mesh.palette,
+                &node_transforms,
+                &joint_transforms,
+            )
+        };
+        let instance_bounds = |instance: MeshInstance| {
+            let mesh = &model.meshes[instance.mesh];
+            model
+                .scene
+                .instance_bounds(instance, &mesh.bounds, &node_transforms, &joint_transforms)
+        };
+
+        let mut morph_weights: Vec<Vec<f32>> = model
+            .meshes
+            .iter()
+            .map(|m| m.morph_weights.clone())
+            .collect();
+        mixer.morph_weights(&model.animations, &model.scene, &mut morph_weights);
+        for (mesh, (morphed, weights)) in model
+            .meshes
+            .iter()
+            .zip(morphed.iter_mut().zip(&morph_weights))
+        {
+            if let Some(morphed) = morphed {
+                morphed.update(&mesh.morph_targets, weights);
+            }
+        }
 
         hid.scan_input();
 
@@ -164,6 +236,49 @@ fn main() {
             break;
         }
 
+        // Pick whatever is under the stylus, with the meshes posed how they're being drawn
+        if hid.keys_down().contains(KeyPad::TOUCH) {
+            let ray = graphics::touch_ray(hid.touch_position(), &Mat4::from(mvp));
+            let hit = instances
+                .iter()
+                .filter_map(|instance| {
+                    let mesh = &model.meshes[instance.mesh];
+                    ray.intersect_aabb(&instance_bounds(*instance))?;
+                    let joints = instance_joints(*instance);
+                    let verts = match &morphed[instance.mesh] {
+                        Some(morphed) => morphed.verts.to_vec(),
+                        None => mesh.verts.to_vertices(),
+                    };
+                    let inds = triangle_list(&mesh.inds.to_indices(), mesh.topology);
+                    let hit = ray.cast_mesh(&verts, &inds, Some(&joints))?;
+                    Some(Hit {
+                        mesh: instance.mesh,
+                        ..hit
+                    })
+                })
+                .min_by(|a, b| a.distance.total_cmp(&b.distance));
+            match hit {
+                Some(hit) => println!(
+                    "\x1b[8;0H Touched: mesh {}, triangle {:<10}",
+                    hit.mesh, hit.triangle
+                ),
+                None => println!("\x1b[8;0H Touched: {:<30}", "nothing"),
+            }
+        }
+
+        for event in mixer.crossed_markers(&model.animations) {
+            println!("\x1b[7;0H Marker: {:<20}", event.marker.name);
+        }
+
+        // Cycle through the model's animations
+        if hid.keys_down().contains(KeyPad::A) {
+            let current = mixer.clips().last().map_or(0, |c| c.animation());
+            mixer.crossfade(
+                animation_player((current + 1) % model.animations.len()),
+                0.5,
+            );
+        }
+
         let used_linear_mem = total_linear_heap_size - LinearAllocator::free_space();
         println!("\x1b[2;0H Frame time: {total_frame_time}ms");
 
@@ -193,16 +308,57 @@ fn main() {
             screen_target.clear(ClearFlags::ALL, CLEAR_COL, 0);
 
             frame.bind_program(&program);
-            frame.set_attr_info(&attr_info);
             frame.select_render_target(&screen_target).unwrap();
-            frame.bind_texture(texture::Index::Texture0, &texture1);
-            frame.set_texenvs(&[textured_stage]);
             frame.bind_vertex_uniform(uniform_proj, mvp);
-            frame.bind_vertex_uniform(uniform_joint, joint_transforms.as_slice());
-            frame.draw_elements(Primitive::Triangles, &model1_info, &model1_inds);
 
-            frame.bind_texture(texture::Index::Texture0, &texture2);
-            frame.draw_elements(Primitive::Triangles, &model2_info, &model2_inds);
+            for instance in &instances {
+                let (mesh, attr_info, info) = &meshes[instance.mesh];
+                if !frustum.intersects_aabb(&instance_bounds(*instance)) {
+                    continue;
+                }
+
+                let verts = match &morphed[instance.mesh] {
+                    Some(morphed) => VertexData::Full(&morphed.verts),
+                    None => mesh.verts,
+                };
+                let morphed_info;
+                let info = match info {
+                    Some(info) => info,
+                    None => {
+                        let mut info = buffer::Info::new();
+                        graphics::add_vertices(&mut info, verts, attr_info);
+                        morphed_info = info;
+                        &morphed_info
+                    }
+                };
+
+                let material = &model.materials[mesh.material as usize];
+                frame.set_attr_info(attr_info);
+                if let Some(texture) = material.texture {
+                    frame.bind_texture(texture::Index::Texture0, &textures[texture as usize]);
+                }
+                frame.set_texenvs(&graphics::material_texenvs(material));
+                graphics::set_material_state(material);
+
+                // Only the mesh's palette, or its node's transform, is uploaded, and packed
+                // positions are turned back into model space by the joint matrices
+                let dequantize = verts.dequantize();
+                let joints = graphics::dequantize_joints(&instance_joints(*instance), &dequantize);
+                frame.bind_vertex_uniform(uniform_joint, joints.as_slice());
+                frame.bind_vertex_uniform(uniform_uv, dequantize.uv_transform());
+                frame.bind_vertex_uniform(
+                    uniform_weight_scale,
+                    Vec4::splat(verts.format().weight_scale()),
+                );
+                let primitive = match mesh.topology {
+                    Topology::Triangles => Primitive::Triangles,
+                    Topology::TriangleStrip => Primitive::TriangleStrip,
+                };
+                match mesh.inds {
+                    IndexData::U16(inds) => frame.draw_elements(primitive, info, inds),
+                    IndexData::U8(inds) => frame.draw_elements(primitive, info, inds),
+                }
+            }
 
             frame
         });
@@ -1,4 +1,6 @@
-use std::thread::JoinHandle;
+use std::{fs::File, io::Read, ops::Deref, path::Path, thread::JoinHandle};
+
+use ctru::linear::LinearAllocator;
 
 /// Spawn a thread with a priority of the current thread + `priority` (lower value means higher priority).
 /// Higher priority threads will always take precendence if they are ready, so setting a lower priority
@@ -32,3 +34,44 @@ where
         f()
     })
 }
+
+/// Bytes in linear memory, so the GPU can read from them, aligned to 4 bytes so they can be cast
+/// to arrays of vertices or indices.
+pub struct LinearBytes {
+    words: Vec<u32, LinearAllocator>,
+    len: usize,
+}
+
+impl LinearBytes {
+    fn zeroed(len: usize) -> Self {
+        let mut words = Vec::with_capacity_in(len.div_ceil(4), LinearAllocator);
+        words.resize(len.div_ceil(4), 0);
+        Self { words, len }
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        let mut out = Self::zeroed(bytes.len());
+        out.bytes_mut().copy_from_slice(bytes);
+        out
+    }
+
+    /// Reads a whole file straight into linear memory
+    pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut out = Self::zeroed(file.metadata()?.len() as usize);
+        file.read_exact(out.bytes_mut())?;
+        Ok(out)
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut bytemuck::cast_slice_mut(&mut self.words)[..self.len]
+    }
+}
+
+impl Deref for LinearBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &bytemuck::cast_slice(&self.words)[..self.len]
+    }
+}
@@ -0,0 +1,303 @@
+//! Bounding volumes for meshes, and testing them against the view frustum so meshes that are
+//! off-screen can be skipped.
+
+use glam::{Mat4, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+
+use crate::{Mesh, Vertex};
+
+/// An axis-aligned bounding box
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// The smallest box around `points`, or `None` if there aren't any
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Aabb> {
+        points.into_iter().fold(None, |aabb, p| {
+            Some(match aabb {
+                Some(Aabb { min, max }) => Aabb {
+                    min: min.min(p),
+                    max: max.max(p),
+                },
+                None => Aabb { min: p, max: p },
+            })
+        })
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// The smallest box around both boxes
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// A box around this one after it's been transformed by `matrix`, which can be bigger than
+    /// the transformed box itself if `matrix` rotates it
+    pub fn transformed(&self, matrix: &Mat4) -> Aabb {
+        let center = matrix.transform_point3(self.center());
+        let half_extents = self.half_extents();
+        // Each of the old axes adds to the new extents however much it points along them
+        let extents = matrix.x_axis.truncate().abs() * half_extents.x
+            + matrix.y_axis.truncate().abs() * half_extents.y
+            + matrix.z_axis.truncate().abs() * half_extents.z;
+        Aabb {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+}
+
+/// A bounding sphere
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Sphere {
+    /// A sphere around `points`, centred on their bounding box. It isn't the smallest possible,
+    /// but is usually close.
+    pub fn from_points(points: &[Vec3]) -> Option<Sphere> {
+        let center = Aabb::from_points(points.iter().copied())?.center();
+        let radius = points
+            .iter()
+            .map(|p| p.distance_squared(center))
+            .fold(0.0, f32::max)
+            .sqrt();
+        Some(Sphere { center, radius })
+    }
+
+    /// A sphere around this one after it's been transformed by `matrix`, using its largest scale
+    pub fn transformed(&self, matrix: &Mat4) -> Sphere {
+        let scale = matrix
+            .x_axis
+            .truncate()
+            .length_squared()
+            .max(matrix.y_axis.truncate().length_squared())
+            .max(matrix.z_axis.truncate().length_squared())
+            .sqrt();
+        Sphere {
+            center: matrix.transform_point3(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// Bounds of the vertices influenced by one joint, in model space in the bind pose
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JointBounds {
+    pub joint: u8,
+    pub aabb: Aabb,
+}
+
+/// How much space a mesh takes up
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MeshBounds {
+    /// Around the mesh in the bind pose
+    pub aabb: Aabb,
+    /// Around the mesh in the bind pose
+    pub sphere: Sphere,
+    /// For each joint that influences the mesh, so the mesh can be bounded in any pose
+    pub joints: Vec<JointBounds>,
+}
+
+impl MeshBounds {
+    pub fn from_vertices(verts: &[Vertex]) -> MeshBounds {
+        let points: Vec<Vec3> = verts.iter().map(|v| v.pos).collect();
+        let mut joints: Vec<JointBounds> = Vec::new();
+        for v in verts {
+            for (joint, weight) in v.joints.into_iter().zip(v.weights.to_array()) {
+                if weight <= 0.0 {
+                    continue;
+                }
+                let point = Aabb {
+                    min: v.pos,
+                    max: v.pos,
+                };
+                match joints.iter_mut().find(|b| b.joint == joint) {
+                    Some(bounds) => bounds.aabb = bounds.aabb.union(&point),
+                    None => joints.push(JointBounds { joint, aabb: point }),
+                }
+            }
+        }
+        joints.sort_by_key(|b| b.joint);
+
+        MeshBounds {
+            aabb: Aabb::from_points(points.iter().copied()).unwrap_or_default(),
+            sphere: Sphere::from_points(&points).unwrap_or_default(),
+            joints,
+        }
+    }
+
+    /// Bounds of `mesh`, including anywhere its morph targets can move the vertices to with
+    /// weights from 0 to 1. The joint bounds are for skeleton joints, even if the mesh has a
+    /// palette.
+    pub fn from_mesh(mesh: &Mesh) -> MeshBounds {
+        let mut bounds = MeshBounds::from_morphed_vertices(mesh);
+        if !mesh.palette.is_empty() {
+            for b in &mut bounds.joints {
+                b.joint = mesh.palette[b.joint as usize];
+            }
+            bounds.joints.sort_by_key(|b| b.joint);
+        }
+        bounds
+    }
+
+    fn from_morphed_vertices(mesh: &Mesh) -> MeshBounds {
+        if mesh.morph_targets.is_empty() {
+            return MeshBounds::from_vertices(&mesh.verts);
+        }
+
+        // Each vertex can move anywhere in the box between all of its targets' negative offsets
+        // and all of their positive ones
+        let extremes: Vec<Vertex> = mesh
+            .verts
+            .iter()
+            .enumerate()
+            .flat_map(|(i, v)| {
+                let offsets = mesh.morph_targets.iter().filter_map(|t| t.positions.get(i));
+                let min: Vec3 = offsets.clone().map(|o| o.min(Vec3::ZERO)).sum();
+                let max: Vec3 = offsets.map(|o| o.max(Vec3::ZERO)).sum();
+                [min, max].map(|offset| Vertex {
+                    pos: v.pos + offset,
+                    ..*v
+                })
+            })
+            .collect();
+        MeshBounds::from_vertices(&extremes)
+    }
+
+    /// A box around the mesh when skinned by `joints`, as returned by
+    /// [`crate::Skeleton::apply_pose_to_joints`]. Every skinned vertex is a weighted average of
+    /// where its joints would each put it, so it's inside the box around what each joint does to
+    /// the vertices it influences.
+    pub fn posed(&self, joints: &[Mat4]) -> Aabb {
+        self.joints
+            .iter()
+            .filter_map(|b| Some(b.aabb.transformed(joints.get(b.joint as usize)?)))
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or(self.aabb)
+    }
+}
+
+/// The space that can be seen through a camera, as 6 planes facing inwards
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    /// Normal in `xyz` and distance in `w`, so a point `p` is in front of a plane if
+    /// `plane.dot(p.extend(1.0)) >= 0.0`. In order: left, right, bottom, top, near, far.
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a projection matrix, or a projection matrix multiplied by view
+    /// and model matrices to get the frustum in that model's space.
+    ///
+    /// Depth is treated as going from -1 to 1 in clip space, which covers the ranges used by both
+    /// glam and citro3d. With a smaller range the near or far plane is a little further out than
+    /// the real one, so some meshes that can't be seen won't be culled, but none that can be will.
+    pub fn from_matrix(matrix: &Mat4) -> Frustum {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| matrix.row(i));
+        let planes = [w + x, w - x, w + y, w - y, w + z, w - z].map(|p| {
+            let length = p.truncate().length();
+            if length > 0.0 {
+                p / length
+            } else {
+                p
+            }
+        });
+        Frustum { planes }
+    }
+
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        self.planes.iter().all(|p| p.dot(point.extend(1.0)) >= 0.0)
+    }
+
+    /// Whether any of `sphere` might be inside the frustum
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes
+            .iter()
+            .all(|p| p.dot(sphere.center.extend(1.0)) >= -sphere.radius)
+    }
+
+    /// Whether any of `aabb` might be inside the frustum. Boxes near the corners of the frustum
+    /// can be outside it and still pass.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|p| {
+            // The corner of the box furthest in front of the plane
+            let corner = Vec3::select(p.truncate().cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            p.dot(corner.extend(1.0)) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Quat;
+
+    use super::*;
+
+    #[test]
+    fn culls_outside_frustum() {
+        let proj = Mat4::perspective_rh_gl(90f32.to_radians(), 1.0, 0.1, 10.0);
+        let frustum = Frustum::from_matrix(&proj);
+
+        let aabb = |center: Vec3| Aabb {
+            min: center - 0.5,
+            max: center + 0.5,
+        };
+        assert!(frustum.intersects_aabb(&aabb(Vec3::new(0.0, 0.0, -5.0))));
+        // Straddling the left plane
+        assert!(frustum.intersects_aabb(&aabb(Vec3::new(-5.2, 0.0, -5.0))));
+        // Behind the camera, off to the side and past the far plane
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(0.0, 0.0, 5.0))));
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(-7.0, 0.0, -5.0))));
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(0.0, 0.0, -12.0))));
+
+        let sphere = |center: Vec3| Sphere {
+            center,
+            radius: 1.0,
+        };
+        assert!(frustum.intersects_sphere(&sphere(Vec3::new(0.0, 5.5, -5.0))));
+        assert!(!frustum.intersects_sphere(&sphere(Vec3::new(0.0, 7.0, -5.0))));
+    }
+
+    #[test]
+    fn posed_bounds_follow_joints() {
+        let vertex = |pos: Vec3, joint: u8| Vertex {
+            pos,
+            joints: [joint, 0, 0],
+            weights: Vec3::X,
+            ..Default::default()
+        };
+        let bounds = MeshBounds::from_vertices(&[
+            vertex(Vec3::new(-1.0, 0.0, 0.0), 0),
+            vertex(Vec3::new(1.0, 0.0, 0.0), 0),
+            vertex(Vec3::new(0.0, 2.0, 0.0), 1),
+        ]);
+        assert_eq!(bounds.aabb.max, Vec3::new(1.0, 2.0, 0.0));
+        assert_eq!(bounds.sphere.center, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(bounds.joints.len(), 2);
+
+        // Joint 1 is moved up and rotated, joint 0 stays put
+        let joints = [
+            Mat4::IDENTITY,
+            Mat4::from_rotation_translation(Quat::from_rotation_z(1.0), Vec3::new(0.0, 3.0, 0.0)),
+        ];
+        let posed = bounds.posed(&joints);
+        let tip = joints[1].transform_point3(Vec3::new(0.0, 2.0, 0.0));
+        assert!(posed.min.cmple(tip).all() && posed.max.cmpge(tip).all());
+        assert!(posed.min.cmple(Vec3::new(-1.0, 0.0, 0.0)).all());
+    }
+}
@@ -0,0 +1,1210 @@
+//! The `.model` file format.
+//!
+//! A file starts with a 12 byte header: the magic bytes `3DSM`, the format version and number of
+//! sections as little-endian `u16`s, and a CRC-32 of everything after the header. This is
+//! followed by a table of sections, each a 4 byte tag and the offset and length of its contents
+//! as little-endian `u32`s. Every section starts on a 16 byte boundary.
+//!
+//! Vertices, indices and texture data are stored as raw little-endian arrays in the same layout as
+//! [`Vertex`] or [`PackedVertex`], `u16` or `u8` and `u8`, so [`ModelView`] can use them in place
+//! without copying. The rest is MessagePack with named fields, so fields can be added to those
+//! types (with `#[serde(default)]`) without bumping the version.
+//!
+//! Older versions are migrated to the current [`Model`] by [`read_model`]:
+//! - Version 0 is a bare MessagePack dump of the model, from before the container existed
+//! - Version 1 stores meshes and textures as MessagePack instead of raw arrays
+//! - Version 2 has no packed vertices
+//! - Version 3 has no scene
+//! - Version 4 has no materials, meshes refer to textures directly
+//! - Version 5 has no 8 bit indices
+
+use std::{fmt, mem::size_of};
+
+use bytemuck::{AnyBitPattern, PodCastError};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    bounds::MeshBounds,
+    layout,
+    material::Material,
+    morph::MorphTarget,
+    optimize::Topology,
+    packed::{self, Dequantize, PackedVertex, VertexFormat},
+    scene::Scene,
+    Animation, Mesh, Model, Skeleton, Texture, Vertex,
+};
+
+pub const MAGIC: [u8; 4] = *b"3DSM";
+/// The version written by [`write_model`], and the newest one [`read_model`] understands
+pub const FORMAT_VERSION: u16 = 6;
+
+const HEADER_LEN: usize = 12;
+const SECTION_ENTRY_LEN: usize = 12;
+const SECTION_ALIGN: usize = 16;
+
+const MESHES: [u8; 4] = *b"MESH";
+const VERTICES: [u8; 4] = *b"VERT";
+const PACKED_VERTICES: [u8; 4] = *b"VPAK";
+const INDICES: [u8; 4] = *b"INDX";
+const SMALL_INDICES: [u8; 4] = *b"IND8";
+const TEXTURES: [u8; 4] = *b"TEXR";
+const TEXTURE_DATA: [u8; 4] = *b"TXDT";
+const SKELETON: [u8; 4] = *b"SKEL";
+const ANIMATIONS: [u8; 4] = *b"ANIM";
+const SCENE: [u8; 4] = *b"SCEN";
+const MATERIALS: [u8; 4] = *b"MATL";
+
+/// Why a model couldn't be read or written
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModelError {
+    /// The file doesn't start with [`MAGIC`] and isn't a legacy model either
+    NotAModel,
+    /// The file was written by a newer version of the preprocessor than this reader supports
+    UnsupportedVersion {
+        version: u16,
+        supported: u16,
+    },
+    /// The file is from an older version that can only be loaded by copying it with
+    /// [`read_model`]
+    RequiresMigration {
+        version: u16,
+    },
+    /// The contents don't match the checksum in the header, so the file is corrupt
+    ChecksumMismatch {
+        expected: u32,
+        found: u32,
+    },
+    /// The file ends before the header, section table or a section does
+    Truncated,
+    /// The file isn't loaded at an address aligned enough for its arrays to be used in place
+    Misaligned,
+    /// A section this version of the format requires isn't in the file
+    MissingSection([u8; 4]),
+    Encode {
+        section: [u8; 4],
+        message: String,
+    },
+    Decode {
+        section: [u8; 4],
+        message: String,
+    },
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::NotAModel => write!(f, "Not a model file"),
+            ModelError::UnsupportedVersion { version, supported } => write!(
+                f,
+                "Model is format version {version} but only up to {supported} is supported, \
+                 try rebuilding the app"
+            ),
+            ModelError::RequiresMigration { version } => write!(
+                f,
+                "Model is format version {version}, which can't be used in place, \
+                 try rebuilding the assets"
+            ),
+            ModelError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "Model is corrupt, checksum is {found:#010x} but should be {expected:#010x}"
+            ),
+            ModelError::Truncated => write!(f, "Model file is cut short"),
+            ModelError::Misaligned => write!(f, "Model data isn't aligned to 4 bytes"),
+            ModelError::MissingSection(tag) => {
+                write!(f, "Model is missing its {} section", tag_name(tag))
+            }
+            ModelError::Encode { section, message } => {
+                write!(
+                    f,
+                    "Couldn't encode {} section: {message}",
+                    tag_name(section)
+                )
+            }
+            ModelError::Decode { section, message } => {
+                write!(
+                    f,
+                    "Couldn't decode {} section: {message}",
+                    tag_name(section)
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+fn tag_name(tag: &[u8; 4]) -> String {
+    String::from_utf8_lossy(tag).into_owned()
+}
+
+/// Where a mesh's vertices and indices are in the `VERT` (or `VPAK` if packed) and `INDX`
+/// sections
+#[derive(Serialize, Deserialize)]
+struct MeshEntry {
+    first_vertex: u32,
+    vertex_count: u32,
+    first_index: u32,
+    index_count: u32,
+    /// Before version 5 this was a texture index, which is also the index of the material made
+    /// for that texture by [`Material::from_textures`]
+    #[serde(alias = "texture")]
+    material: u8,
+    #[serde(default)]
+    format: VertexFormat,
+    #[serde(default)]
+    dequantize: Dequantize,
+    /// Missing from files written before bounds were added, so they're calculated on load
+    #[serde(default)]
+    bounds: Option<MeshBounds>,
+    #[serde(default)]
+    morph_targets: Vec<MorphTarget>,
+    #[serde(default)]
+    morph_weights: Vec<f32>,
+    #[serde(default)]
+    palette: Vec<u8>,
+    /// Whether the indices are in the `INDX` or `IND8` section
+    #[serde(default)]
+    index_format: IndexFormat,
+    #[serde(default)]
+    topology: Topology,
+}
+
+/// How a mesh's indices are stored
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IndexFormat {
+    #[default]
+    U16,
+    /// For meshes with at most 256 vertices, to save space
+    U8,
+}
+
+impl IndexFormat {
+    /// The smallest format that can index `vertex_count` vertices
+    pub fn for_vertex_count(vertex_count: usize) -> IndexFormat {
+        if vertex_count <= 1 << 8 {
+            IndexFormat::U8
+        } else {
+            IndexFormat::U16
+        }
+    }
+}
+
+/// Where a texture's data is in the `TXDT` section
+#[derive(Serialize, Deserialize)]
+struct TextureEntry {
+    offset: u32,
+    len: u32,
+    width: u16,
+    height: u16,
+}
+
+/// Vertices borrowed from a model file, in the format they were stored in
+#[derive(Clone, Copy, Debug)]
+pub enum VertexData<'a> {
+    Full(&'a [Vertex]),
+    Packed(&'a [PackedVertex], Dequantize),
+}
+
+impl VertexData<'_> {
+    pub fn format(&self) -> VertexFormat {
+        match self {
+            VertexData::Full(_) => VertexFormat::Full,
+            VertexData::Packed(..) => VertexFormat::Packed,
+        }
+    }
+
+    /// How to get the original positions and texture coordinates back, which does nothing for
+    /// full vertices
+    pub fn dequantize(&self) -> Dequantize {
+        match self {
+            VertexData::Full(_) => Dequantize::default(),
+            VertexData::Packed(_, dequantize) => *dequantize,
+        }
+    }
+
+    /// Copies the vertices out, unpacking them if needed
+    pub fn to_vertices(&self) -> Vec<Vertex> {
+        match self {
+            VertexData::Full(verts) => verts.to_vec(),
+            VertexData::Packed(verts, dequantize) => {
+                verts.iter().map(|v| v.unpack(dequantize)).collect()
+            }
+        }
+    }
+}
+
+/// Indices borrowed from a model file, in the format they were stored in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexData<'a> {
+    U16(&'a [u16]),
+    U8(&'a [u8]),
+}
+
+impl IndexData<'_> {
+    pub fn format(&self) -> IndexFormat {
+        match self {
+            IndexData::U16(_) => IndexFormat::U16,
+            IndexData::U8(_) => IndexFormat::U8,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            IndexData::U16(inds) => inds.len(),
+            IndexData::U8(inds) => inds.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies the indices out, widening them if needed
+    pub fn to_indices(&self) -> Vec<u16> {
+        match self {
+            IndexData::U16(inds) => inds.to_vec(),
+            IndexData::U8(inds) => inds.iter().map(|i| u16::from(*i)).collect(),
+        }
+    }
+}
+
+/// A mesh whose vertices and indices are borrowed from a model file
+#[derive(Clone, Debug)]
+pub struct MeshView<'a> {
+    pub verts: VertexData<'a>,
+    pub inds: IndexData<'a>,
+    /// Index into [`ModelView::materials`]
+    pub material: u8,
+    pub bounds: MeshBounds,
+    pub morph_targets: Vec<MorphTarget>,
+    pub morph_weights: Vec<f32>,
+    /// See [`Mesh::palette`]
+    pub palette: Vec<u8>,
+    pub topology: Topology,
+}
+
+/// A texture whose data is borrowed from a model file
+#[derive(Clone, Copy, Debug)]
+pub struct TextureView<'a> {
+    pub data: &'a [u8],
+    pub width: u16,
+    pub height: u16,
+}
+
+/// A model that uses the vertex, index and texture data of a file in place, so if the file is
+/// read into linear memory they can be given straight to the GPU. Only the skeleton and
+/// animations are decoded.
+#[derive(Clone, Debug)]
+pub struct ModelView<'a> {
+    pub meshes: Vec<MeshView<'a>>,
+    pub textures: Vec<TextureView<'a>>,
+    pub materials: Vec<Material>,
+    pub skeleton: Skeleton,
+    pub animations: Vec<Animation>,
+    pub scene: Scene,
+}
+
+impl<'a> ModelView<'a> {
+    /// Reads a model in the current format version. `bytes` must be aligned to 4 bytes.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ModelError> {
+        if !bytes.starts_with(&MAGIC) {
+            return Err(if is_legacy(bytes) {
+                ModelError::RequiresMigration { version: 0 }
+            } else {
+                ModelError::NotAModel
+            });
+        }
+        let container = Container::parse(bytes)?;
+        if container.version < FORMAT_VERSION {
+            return Err(ModelError::RequiresMigration {
+                version: container.version,
+            });
+        }
+        ModelView::parse_version(&container)
+    }
+
+    /// Reads a model in any version from 2 on, filling in anything older versions don't have.
+    /// Only [`read_model`] uses this for older versions, as it copies everything out anyway.
+    fn parse_version(container: &Container<'a>) -> Result<Self, ModelError> {
+        let vertices: &[Vertex] = cast(container.section(VERTICES)?)?;
+        let packed_vertices: &[PackedVertex] = if container.version >= 3 {
+            cast(container.section(PACKED_VERTICES)?)?
+        } else {
+            &[]
+        };
+        let indices: &[u16] = cast(container.section(INDICES)?)?;
+        let small_indices: &[u8] = if container.version >= 6 {
+            container.section(SMALL_INDICES)?
+        } else {
+            &[]
+        };
+        let texture_data = container.section(TEXTURE_DATA)?;
+
+        let meshes = container
+            .decode::<Vec<MeshEntry>>(MESHES)?
+            .into_iter()
+            .map(|m| {
+                let verts = match m.format {
+                    VertexFormat::Full => {
+                        VertexData::Full(range(vertices, m.first_vertex, m.vertex_count)?)
+                    }
+                    VertexFormat::Packed => VertexData::Packed(
+                        range(packed_vertices, m.first_vertex, m.vertex_count)?,
+                        m.dequantize,
+                    ),
+                };
+                Ok(MeshView {
+                    verts,
+                    inds: match m.index_format {
+                        IndexFormat::U16 => {
+                            IndexData::U16(range(indices, m.first_index, m.index_count)?)
+                        }
+                        IndexFormat::U8 => {
+                            IndexData::U8(range(small_indices, m.first_index, m.index_count)?)
+                        }
+                    },
+                    material: m.material,
+                    bounds: m
+                        .bounds
+                        .unwrap_or_else(|| MeshBounds::from_vertices(&verts.to_vertices())),
+                    morph_targets: m.morph_targets,
+                    morph_weights: m.morph_weights,
+                    palette: m.palette,
+                    topology: m.topology,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let textures: Vec<_> = container
+            .decode::<Vec<TextureEntry>>(TEXTURES)?
+            .into_iter()
+            .map(|t| {
+                Ok(TextureView {
+                    data: range(texture_data, t.offset, t.len)?,
+                    width: t.width,
+                    height: t.height,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        let materials = if container.version >= 5 {
+            container.decode(MATERIALS)?
+        } else {
+            Material::from_textures(textures.len())
+        };
+
+        Ok(ModelView {
+            meshes,
+            textures,
+            materials,
+            skeleton: container.decode(SKELETON)?,
+            animations: container.decode(ANIMATIONS)?,
+            scene: if container.version >= 4 {
+                container.decode(SCENE)?
+            } else {
+                Scene::default()
+            },
+        })
+    }
+
+    /// Copies everything out of the file
+    pub fn to_model(&self) -> Model {
+        Model {
+            meshes: self
+                .meshes
+                .iter()
+                .map(|m| Mesh {
+                    verts: m.verts.to_vertices(),
+                    inds: m.inds.to_indices(),
+                    material: m.material,
+                    format: m.verts.format(),
+                    bounds: m.bounds.clone(),
+                    morph_targets: m.morph_targets.clone(),
+                    morph_weights: m.morph_weights.clone(),
+                    palette: m.palette.clone(),
+                    topology: m.topology,
+                })
+                .collect(),
+            textures: self
+                .textures
+                .iter()
+                .map(|t| Texture {
+                    data: t.data.to_vec(),
+                    width: t.width,
+                    height: t.height,
+                })
+                .collect(),
+            materials: self.materials.clone(),
+            skeleton: self.skeleton.clone(),
+            animations: self.animations.clone(),
+            scene: self.scene.clone(),
+        }
+    }
+}
+
+fn cast<T: AnyBitPattern>(bytes: &[u8]) -> Result<&[T], ModelError> {
+    bytemuck::try_cast_slice(bytes).map_err(|e| match e {
+        PodCastError::TargetAlignmentGreaterAndInputNotAligned => ModelError::Misaligned,
+        _ => ModelError::Truncated,
+    })
+}
+
+fn range<T>(items: &[T], first: u32, count: u32) -> Result<&[T], ModelError> {
+    let first = first as usize;
+    first
+        .checked_add(count as usize)
+        .and_then(|end| items.get(first..end))
+        .ok_or(ModelError::Truncated)
+}
+
+/// Encodes `model` in the current format version
+pub fn write_model(model: &Model) -> Result<Vec<u8>, ModelError> {
+    let mut vertices = Vec::new();
+    let mut packed_vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut small_indices = Vec::new();
+    let meshes: Vec<MeshEntry> = model
+        .meshes
+        .iter()
+        .map(|m| {
+            let mut entry = MeshEntry {
+                first_vertex: (vertices.len() / size_of::<Vertex>()) as u32,
+                vertex_count: m.verts.len() as u32,
+                first_index: (indices.len() / size_of::<u16>()) as u32,
+                index_count: m.inds.len() as u32,
+                material: m.material,
+                format: m.format,
+                dequantize: Dequantize::default(),
+                bounds: Some(m.bounds.clone()),
+                morph_targets: m.morph_targets.clone(),
+                morph_weights: m.morph_weights.clone(),
+                palette: m.palette.clone(),
+                index_format: IndexFormat::for_vertex_count(m.verts.len()),
+                topology: m.topology,
+            };
+            match m.format {
+                VertexFormat::Full => vertices.extend(layout::vertex_bytes(&m.verts)),
+                VertexFormat::Packed => {
+                    let (packed, dequantize) = packed::pack(&m.verts);
+                    entry.first_vertex = (packed_vertices.len() / size_of::<PackedVertex>()) as u32;
+                    entry.dequantize = dequantize;
+                    packed_vertices.extend(layout::vertex_bytes(&packed));
+                }
+            }
+            match entry.index_format {
+                IndexFormat::U16 => indices.extend(m.inds.iter().flat_map(|i| i.to_le_bytes())),
+                IndexFormat::U8 => {
+                    entry.first_index = small_indices.len() as u32;
+                    small_indices.extend(m.inds.iter().map(|i| *i as u8));
+                }
+            }
+            entry
+        })
+        .collect();
+
+    let mut texture_data = Vec::new();
+    let textures: Vec<TextureEntry> = model
+        .textures
+        .iter()
+        .map(|t| {
+            texture_data.resize(texture_data.len().next_multiple_of(SECTION_ALIGN), 0);
+            let entry = TextureEntry {
+                offset: texture_data.len() as u32,
+                len: t.data.len() as u32,
+                width: t.width,
+                height: t.height,
+            };
+            texture_data.extend_from_slice(&t.data);
+            entry
+        })
+        .collect();
+
+    Ok(write_container(
+        FORMAT_VERSION,
+        &[
+            (MESHES, encode(MESHES, &meshes)?),
+            (VERTICES, vertices),
+            (PACKED_VERTICES, packed_vertices),
+            (INDICES, indices),
+            (SMALL_INDICES, small_indices),
+            (TEXTURES, encode(TEXTURES, &textures)?),
+            (TEXTURE_DATA, texture_data),
+            (SKELETON, encode(SKELETON, &model.skeleton)?),
+            (ANIMATIONS, encode(ANIMATIONS, &model.animations)?),
+            (SCENE, encode(SCENE, &model.scene)?),
+            (MATERIALS, encode(MATERIALS, &model.materials)?),
+        ],
+    ))
+}
+
+fn write_container(version: u16, sections: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&version.to_le_bytes());
+    out.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+    // Checksum is filled in once everything else is written
+    out.extend_from_slice(&[0; 4]);
+
+    let mut offset = HEADER_LEN + sections.len() * SECTION_ENTRY_LEN;
+    for (tag, contents) in sections {
+        offset = offset.next_multiple_of(SECTION_ALIGN);
+        out.extend_from_slice(tag);
+        out.extend_from_slice(&(offset as u32).to_le_bytes());
+        out.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        offset += contents.len();
+    }
+    for (_, contents) in sections {
+        out.resize(out.len().next_multiple_of(SECTION_ALIGN), 0);
+        out.extend_from_slice(contents);
+    }
+
+    let checksum = crc32(&out[HEADER_LEN..]);
+    out[8..12].copy_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+/// Decodes a model written by any format version up to [`FORMAT_VERSION`], migrating older
+/// versions to the current [`Model`]. Unlike [`ModelView::parse`], `bytes` can have any alignment.
+pub fn read_model(bytes: &[u8]) -> Result<Model, ModelError> {
+    if !bytes.starts_with(&MAGIC) {
+        return rmp_serde::from_slice::<v0::Model>(bytes)
+            .map(Model::from)
+            .map_err(|_| ModelError::NotAModel);
+    }
+
+    let container = Container::parse(bytes)?;
+    match container.version {
+        1 => {
+            let textures: Vec<Texture> = container
+                .decode::<Vec<v0::Texture>>(TEXTURES)?
+                .into_iter()
+                .map(Texture::from)
+                .collect();
+            Ok(Model {
+                meshes: container
+                    .decode::<Vec<v0::Mesh>>(MESHES)?
+                    .into_iter()
+                    .map(Mesh::from)
+                    .collect(),
+                materials: Material::from_textures(textures.len()),
+                textures,
+                skeleton: container.decode(SKELETON)?,
+                animations: container.decode(ANIMATIONS)?,
+                scene: Scene::default(),
+            })
+        }
+        2..=FORMAT_VERSION => match ModelView::parse_version(&container) {
+            Err(ModelError::Misaligned) => {
+                let mut aligned = vec![0u32; bytes.len().div_ceil(4)];
+                let aligned = &mut bytemuck::cast_slice_mut(&mut aligned)[..bytes.len()];
+                aligned.copy_from_slice(bytes);
+                ModelView::parse_version(&Container::parse(aligned)?).map(|view| view.to_model())
+            }
+            view => view.map(|view| view.to_model()),
+        },
+        version => Err(ModelError::UnsupportedVersion {
+            version,
+            supported: FORMAT_VERSION,
+        }),
+    }
+}
+
+/// The version of the format `bytes` was written in, without decoding the rest of it
+pub fn read_version(bytes: &[u8]) -> Result<u16, ModelError> {
+    if !bytes.starts_with(&MAGIC) {
+        return if is_legacy(bytes) {
+            Ok(0)
+        } else {
+            Err(ModelError::NotAModel)
+        };
+    }
+    Container::parse(bytes).map(|c| c.version)
+}
+
+/// Whether `bytes`, which don't start with [`MAGIC`], are a model from before the container
+/// existed
+fn is_legacy(bytes: &[u8]) -> bool {
+    rmp_serde::from_slice::<v0::Model>(bytes).is_ok()
+}
+
+/// A checked file, with the location of each section
+struct Container<'a> {
+    version: u16,
+    sections: Vec<([u8; 4], &'a [u8])>,
+}
+
+impl<'a> Container<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self, ModelError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(ModelError::Truncated);
+        }
+        let read_u16 = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let read_u32 =
+            |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().expect("Slice is 4 bytes"));
+
+        let version = read_u16(4);
+        if version > FORMAT_VERSION {
+            return Err(ModelError::UnsupportedVersion {
+                version,
+                supported: FORMAT_VERSION,
+            });
+        }
+
+        let expected = read_u32(8);
+        let found = crc32(&bytes[HEADER_LEN..]);
+        if expected != found {
+            return Err(ModelError::ChecksumMismatch { expected, found });
+        }
+
+        let count = read_u16(6) as usize;
+        if bytes.len() < HEADER_LEN + count * SECTION_ENTRY_LEN {
+            return Err(ModelError::Truncated);
+        }
+        let sections = (0..count)
+            .map(|i| {
+                let entry = HEADER_LEN + i * SECTION_ENTRY_LEN;
+                let tag = bytes[entry..entry + 4]
+                    .try_into()
+                    .expect("Slice is 4 bytes");
+                let offset = read_u32(entry + 4) as usize;
+                let len = read_u32(entry + 8) as usize;
+                let contents = offset
+                    .checked_add(len)
+                    .and_then(|end| bytes.get(offset..end))
+                    .ok_or(ModelError::Truncated)?;
+                Ok((tag, contents))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Container { version, sections })
+    }
+
+    /// The contents of the first section with `tag`. Unknown sections are ignored, so newer
+    /// files can carry extra data older readers don't need.
+    fn section(&self, tag: [u8; 4]) -> Result<&'a [u8], ModelError> {
+        self.sections
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, contents)| *contents)
+            .ok_or(ModelError::MissingSection(tag))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, tag: [u8; 4]) -> Result<T, ModelError> {
+        rmp_serde::from_slice(self.section(tag)?).map_err(|e| ModelError::Decode {
+            section: tag,
+            message: e.to_string(),
+        })
+    }
+}
+
+fn encode<T: Serialize>(tag: [u8; 4], value: &T) -> Result<Vec<u8>, ModelError> {
+    rmp_serde::to_vec_named(value).map_err(|e| ModelError::Encode {
+        section: tag,
+        message: e.to_string(),
+    })
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE), the same as zip and png use
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, b| {
+        CRC32_TABLE[((crc ^ *b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Meshes, textures and the whole model as they were stored in versions 0 and 1, kept so old
+/// files can still be read. These must not change.
+mod v0 {
+    use glam::{Mat4, Quat, Vec2, Vec3};
+    use serde::Deserialize;
+
+    use crate::{Interpolation, Track};
+
+    #[derive(Deserialize)]
+    pub struct Vertex {
+        pub pos: Vec3,
+        pub norm: Vec3,
+        pub tc: Vec2,
+        pub joints: [u8; 3],
+        pub weights: Vec3,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Mesh {
+        pub verts: Vec<Vertex>,
+        pub inds: Vec<u16>,
+        pub texture: u8,
+    }
+
+    #[derive(Deserialize)]
+    pub struct JointTransform {
+        pub pos: Vec3,
+        pub rot: Quat,
+        pub scale: Vec3,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Joint {
+        pub index: u8,
+        pub name: String,
+        pub base_transform: JointTransform,
+        pub inverse_bind_matrix: Mat4,
+        pub children: Vec<u8>,
+        pub parent: Option<u8>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Skeleton {
+        pub joints: Vec<Joint>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Texture {
+        pub data: Vec<u8>,
+        pub width: u16,
+        pub height: u16,
+    }
+
+    #[derive(Deserialize)]
+    pub struct JointAnimation {
+        pub translations: Vec<(f32, Vec3)>,
+        pub rotations: Vec<(f32, Quat)>,
+        pub scales: Vec<(f32, Vec3)>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Animation {
+        pub name: String,
+        pub joints: Vec<JointAnimation>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Model {
+        pub meshes: Vec<Mesh>,
+        pub textures: Vec<Texture>,
+        pub skeleton: Skeleton,
+        pub animations: Vec<Animation>,
+    }
+
+    impl From<Mesh> for crate::Mesh {
+        fn from(m: Mesh) -> Self {
+            let verts: Vec<crate::Vertex> = m
+                .verts
+                .into_iter()
+                .map(|v| crate::Vertex {
+                    pos: v.pos,
+                    norm: v.norm,
+                    tc: v.tc,
+                    joints: v.joints,
+                    weights: v.weights,
+                })
+                .collect();
+            crate::Mesh {
+                bounds: crate::bounds::MeshBounds::from_vertices(&verts),
+                verts,
+                inds: m.inds,
+                material: m.texture,
+                format: crate::packed::VertexFormat::Full,
+                morph_targets: Vec::new(),
+                morph_weights: Vec::new(),
+                palette: Vec::new(),
+                topology: Default::default(),
+            }
+        }
+    }
+
+    impl From<Texture> for crate::Texture {
+        fn from(t: Texture) -> Self {
+            crate::Texture {
+                data: t.data,
+                width: t.width,
+                height: t.height,
+            }
+        }
+    }
+
+    impl From<JointTransform> for crate::JointTransform {
+        fn from(t: JointTransform) -> Self {
+            crate::JointTransform {
+                pos: t.pos,
+                rot: t.rot,
+                scale: t.scale,
+            }
+        }
+    }
+
+    impl From<Model> for crate::Model {
+        fn from(model: Model) -> Self {
+            crate::Model {
+                meshes: model.meshes.into_iter().map(Into::into).collect(),
+                materials: crate::material::Material::from_textures(model.textures.len()),
+                textures: model.textures.into_iter().map(Into::into).collect(),
+                skeleton: crate::Skeleton {
+                    joints: model
+                        .skeleton
+                        .joints
+                        .into_iter()
+                        .map(|j| crate::Joint {
+                            index: j.index,
+                            name: j.name,
+                            base_transform: j.base_transform.into(),
+                            inverse_bind_matrix: j.inverse_bind_matrix,
+                            children: j.children,
+                            parent: j.parent,
+                        })
+                        .collect(),
+                },
+                animations: model
+                    .animations
+                    .into_iter()
+                    .map(|a| crate::Animation {
+                        name: a.name,
+                        joints: a
+                            .joints
+                            .into_iter()
+                            .map(|j| crate::JointAnimation {
+                                translations: Track::new(Interpolation::Linear, j.translations),
+                                rotations: Track::new(Interpolation::Linear, j.rotations),
+                                scales: Track::new(Interpolation::Linear, j.scales),
+                            })
+                            .collect(),
+                        additive: false,
+                        markers: Vec::new(),
+                        root_motion: None,
+                        morph_weights: Vec::new(),
+                    })
+                    .collect(),
+                scene: crate::scene::Scene::default(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use glam::Vec3;
+
+    use super::*;
+
+    fn model() -> Model {
+        let verts = vec![
+            Vertex {
+                pos: Vec3::X,
+                joints: [1, 2, 3],
+                ..Default::default()
+            };
+            3
+        ];
+        let mesh = Mesh {
+            bounds: MeshBounds::from_vertices(&verts),
+            verts,
+            inds: vec![0, 1, 2],
+            format: VertexFormat::Full,
+            ..Default::default()
+        };
+        Model {
+            meshes: vec![
+                mesh.clone(),
+                Mesh {
+                    format: VertexFormat::Packed,
+                    ..mesh
+                },
+            ],
+            textures: vec![Texture {
+                data: vec![0xFF; 16],
+                width: 2,
+                height: 2,
+            }],
+            materials: vec![Material {
+                texture: Some(0),
+                double_sided: true,
+                ..Default::default()
+            }],
+            skeleton: Skeleton { joints: Vec::new() },
+            animations: Vec::new(),
+            scene: Scene::default(),
+        }
+    }
+
+    #[test]
+    fn round_trips_and_detects_problems() {
+        let mut bytes = write_model(&model()).unwrap();
+        assert_eq!(read_version(&bytes), Ok(FORMAT_VERSION));
+        assert!(read_model(&bytes).is_ok());
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(matches!(
+            read_model(&bytes),
+            Err(ModelError::ChecksumMismatch { .. })
+        ));
+
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            read_model(&bytes).err(),
+            Some(ModelError::UnsupportedVersion {
+                version: FORMAT_VERSION + 1,
+                supported: FORMAT_VERSION
+            })
+        );
+
+        assert_eq!(read_model(&[1, 2, 3]).err(), Some(ModelError::NotAModel));
+        assert_eq!(
+            ModelView::parse(&[1, 2, 3]).err(),
+            Some(ModelError::NotAModel)
+        );
+        assert_eq!(read_version(&[1, 2, 3]), Err(ModelError::NotAModel));
+
+        // An empty model from before the container, with no meshes, textures, joints or
+        // animations
+        let empty: Vec<u8> = Vec::new();
+        let legacy = rmp_serde::to_vec(&(&empty, &empty, (&empty,), &empty)).unwrap();
+        assert_eq!(
+            ModelView::parse(&legacy).err(),
+            Some(ModelError::RequiresMigration { version: 0 })
+        );
+        assert_eq!(read_version(&legacy), Ok(0));
+    }
+
+    #[test]
+    fn view_uses_file_in_place() {
+        let mut model = model();
+        // Too many vertices for 8 bit indices
+        model.meshes.push(Mesh {
+            verts: vec![Vertex::default(); 300],
+            inds: vec![0, 299, 1],
+            ..model.meshes[0].clone()
+        });
+        let bytes = write_model(&model).unwrap();
+        let mut aligned = vec![0u32; bytes.len().div_ceil(4)];
+        let aligned = &mut bytemuck::cast_slice_mut(&mut aligned)[..bytes.len()];
+        aligned.copy_from_slice(&bytes);
+
+        let view = ModelView::parse(aligned).unwrap();
+        let VertexData::Full(verts) = view.meshes[0].verts else {
+            panic!("First mesh isn't packed");
+        };
+        assert!(aligned.as_ptr_range().contains(&verts.as_ptr().cast()));
+        assert_eq!(verts[2].pos, Vec3::X);
+        assert_eq!(verts[2].joints, [1, 2, 3]);
+        assert_eq!(view.meshes[0].inds, IndexData::U8(&[0, 1, 2]));
+        assert_eq!(view.meshes[2].inds, IndexData::U16(&[0, 299, 1]));
+        assert_eq!(view.meshes[0].bounds.aabb.max, Vec3::X);
+        assert_eq!(view.textures[0].data, &[0xFF; 16]);
+        assert_eq!(view.materials[0].texture, Some(0));
+        assert!(view.materials[0].double_sided);
+
+        let VertexData::Packed(packed, dequantize) = view.meshes[1].verts else {
+            panic!("Second mesh is packed");
+        };
+        assert_eq!(packed[2].joints, [1, 2, 3]);
+        assert!(packed[2].unpack(&dequantize).pos.abs_diff_eq(Vec3::X, 1e-4));
+
+        // Reading a copy that isn't aligned still works
+        let mut shifted = vec![0; bytes.len() + 1];
+        shifted[1..].copy_from_slice(&bytes);
+        assert_eq!(read_model(&shifted[1..]).unwrap().meshes[0].inds, [0, 1, 2]);
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        #[derive(Serialize)]
+        struct V1Mesh<'a> {
+            verts: &'a [Vertex],
+            inds: &'a [u16],
+            texture: u8,
+        }
+
+        let model = model();
+        let meshes: Vec<_> = model
+            .meshes
+            .iter()
+            .map(|m| V1Mesh {
+                verts: &m.verts,
+                inds: &m.inds,
+                texture: 0,
+            })
+            .collect();
+        let bytes = write_container(
+            1,
+            &[
+                (MESHES, encode(MESHES, &meshes).unwrap()),
+                (TEXTURES, encode(TEXTURES, &model.textures).unwrap()),
+                (SKELETON, encode(SKELETON, &model.skeleton).unwrap()),
+                (ANIMATIONS, encode(ANIMATIONS, &model.animations).unwrap()),
+            ],
+        );
+        assert_eq!(
+            ModelView::parse(&bytes).err(),
+            Some(ModelError::RequiresMigration { version: 1 })
+        );
+
+        let migrated = read_model(&bytes).unwrap();
+        assert_eq!(migrated.meshes[0].verts[0].joints, [1, 2, 3]);
+        assert_eq!(migrated.textures[0].width, 2);
+        assert_eq!(migrated.meshes[0].material, 0);
+        assert_eq!(migrated.materials[0].texture, Some(0));
+    }
+
+    /// `model` as it would have been written by `version`, from 2 up. Meshes are all stored as
+    /// full vertices with 16 bit indices, and sections `version` didn't have are left out.
+    fn write_old_version(model: &Model, version: u16) -> Vec<u8> {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let meshes: Vec<BTreeMap<&str, u32>> = model
+            .meshes
+            .iter()
+            .map(|m| {
+                let material = if version >= 5 { "material" } else { "texture" };
+                let entry = BTreeMap::from([
+                    (
+                        "first_vertex",
+                        (vertices.len() / size_of::<Vertex>()) as u32,
+                    ),
+                    ("vertex_count", m.verts.len() as u32),
+                    ("first_index", (indices.len() / size_of::<u16>()) as u32),
+                    ("index_count", m.inds.len() as u32),
+                    (material, u32::from(m.material)),
+                ]);
+                vertices.extend(layout::vertex_bytes(&m.verts));
+                indices.extend(m.inds.iter().flat_map(|i| i.to_le_bytes()));
+                entry
+            })
+            .collect();
+
+        // Everything else is stored the same as it is now
+        let current = write_model(model).unwrap();
+        let current = Container::parse(&current).unwrap();
+        let mut sections = vec![
+            (MESHES, encode(MESHES, &meshes).unwrap()),
+            (VERTICES, vertices),
+            (INDICES, indices),
+        ];
+        let mut tags = vec![TEXTURES, TEXTURE_DATA, SKELETON, ANIMATIONS];
+        if version >= 3 {
+            tags.push(PACKED_VERTICES);
+        }
+        if version >= 4 {
+            tags.push(SCENE);
+        }
+        if version >= 5 {
+            tags.push(MATERIALS);
+        }
+        sections.extend(
+            tags.into_iter()
+                .map(|tag| (tag, current.section(tag).unwrap().to_vec())),
+        );
+        write_container(version, &sections)
+    }
+
+    #[test]
+    fn migrates_version_2() {
+        let bytes = write_old_version(&model(), 2);
+        assert_eq!(
+            ModelView::parse(&bytes).err(),
+            Some(ModelError::RequiresMigration { version: 2 })
+        );
+
+        let migrated = read_model(&bytes).unwrap();
+        assert_eq!(migrated.meshes.len(), 2);
+        assert!(migrated
+            .meshes
+            .iter()
+            .all(|m| m.format == VertexFormat::Full));
+        assert_eq!(migrated.meshes[1].verts[2].joints, [1, 2, 3]);
+        assert_eq!(migrated.meshes[1].inds, [0, 1, 2]);
+        // Bounds weren't stored yet, so they're calculated
+        assert_eq!(migrated.meshes[0].bounds.aabb.max, Vec3::X);
+        assert_eq!(migrated.textures[0].data, [0xFF; 16]);
+    }
+
+    #[test]
+    fn migrates_version_3() {
+        let mut model = model();
+        model.scene.nodes.push(crate::scene::Node {
+            name: "Root".to_string(),
+            meshes: vec![0, 1],
+            ..Default::default()
+        });
+        let bytes = write_old_version(&model, 3);
+
+        let migrated = read_model(&bytes).unwrap();
+        assert_eq!(migrated.meshes.len(), 2);
+        assert_eq!(migrated.meshes[0].verts[2].pos, Vec3::X);
+        // There was no scene yet
+        assert!(migrated.scene.nodes.is_empty());
+    }
+
+    #[test]
+    fn migrates_version_4() {
+        let mut model = model();
+        model.meshes[1].material = 1;
+        model.textures.push(model.textures[0].clone());
+        model.scene.nodes.push(crate::scene::Node {
+            name: "Root".to_string(),
+            meshes: vec![0, 1],
+            ..Default::default()
+        });
+        let bytes = write_old_version(&model, 4);
+
+        let migrated = read_model(&bytes).unwrap();
+        assert_eq!(migrated.scene.nodes[0].name, "Root");
+        // Meshes referred to textures, which each get a material of their own
+        assert_eq!(migrated.meshes[1].material, 1);
+        assert_eq!(migrated.materials.len(), 2);
+        assert_eq!(migrated.materials[1].texture, Some(1));
+        assert!(!migrated.materials[0].double_sided);
+    }
+
+    #[test]
+    fn migrates_version_5() {
+        let bytes = write_old_version(&model(), 5);
+
+        let migrated = read_model(&bytes).unwrap();
+        // Every mesh had 16 bit indices
+        assert_eq!(migrated.meshes[0].inds, [0, 1, 2]);
+        assert_eq!(migrated.meshes[1].verts[2].joints, [1, 2, 3]);
+        assert_eq!(migrated.materials[0].texture, Some(0));
+        assert!(migrated.materials[0].double_sided);
+
+        // Writing it again uses 8 bit indices for the small meshes
+        let rewritten = write_model(&migrated).unwrap();
+        let mut aligned = vec![0u32; rewritten.len().div_ceil(4)];
+        let aligned = &mut bytemuck::cast_slice_mut(&mut aligned)[..rewritten.len()];
+        aligned.copy_from_slice(&rewritten);
+        let view = ModelView::parse(aligned).unwrap();
+        assert_eq!(view.meshes[0].inds, IndexData::U8(&[0, 1, 2]));
+    }
+
+    #[test]
+    fn reads_legacy_model() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../app/romfs/Bash_3DS.model");
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(read_version(&bytes), Ok(0));
+
+        let model = read_model(&bytes).unwrap();
+        assert_eq!(model.meshes.len(), 2);
+        assert_eq!(model.skeleton.joints.len(), 16);
+        assert_eq!(model.animations[0].name, "Flying");
+    }
+}
@@ -0,0 +1,270 @@
+//! Making up attributes a model was exported without: normals from the shape of the mesh, and
+//! texture coordinates by projecting it onto a plane or a box.
+//!
+//! Both work on each corner of each triangle, so a vertex shared by triangles that need
+//! different values (across a crease, or on different sides of a box) is split into copies.
+
+use std::collections::HashMap;
+
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::{bounds::Aabb, Mesh, Vertex};
+
+/// How normals are made for meshes that don't have any
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalMode {
+    /// Averaged across triangles, weighted by their area and the angle of their corner, except
+    /// between triangles at more than `crease_angle` degrees to each other
+    Smooth {
+        #[serde(default = "default_crease_angle")]
+        crease_angle: f32,
+    },
+    /// Each triangle's own normal, so every triangle is shaded flat
+    Flat,
+}
+
+fn default_crease_angle() -> f32 {
+    60.0
+}
+
+impl Default for NormalMode {
+    fn default() -> Self {
+        NormalMode::Smooth {
+            crease_angle: default_crease_angle(),
+        }
+    }
+}
+
+/// How texture coordinates are made for meshes that don't have any
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UvProjection {
+    /// Left at 0
+    #[default]
+    None,
+    /// Projected straight onto the plane the mesh is flattest in, stretched over the texture
+    Planar,
+    /// Each triangle projected onto whichever side of the mesh's bounding box it faces most
+    Box,
+}
+
+/// Gives every corner of the triangles of `inds` its value from `corner_values`, copying a
+/// vertex when its corners need different values. Like [`crate::partition::split_indices`],
+/// `inds` index into `mesh`'s vertices instead of its own indices.
+fn set_corners<T: Copy + PartialEq>(
+    mesh: &mut Mesh,
+    inds: &mut [u32],
+    corner_values: &[T],
+    set: impl Fn(&mut Vertex, T),
+) {
+    // The values given to each original vertex, and the vertex given each one
+    let mut copies: HashMap<u32, Vec<(T, u32)>> = HashMap::new();
+    for (i, value) in inds.iter_mut().zip(corner_values) {
+        let original = *i;
+        let existing = copies.entry(original).or_default();
+        if let Some((_, copy)) = existing.iter().find(|(v, _)| v == value) {
+            *i = *copy;
+            continue;
+        }
+
+        let copy = if existing.is_empty() {
+            original
+        } else {
+            mesh.verts.push(mesh.verts[original as usize]);
+            for target in &mut mesh.morph_targets {
+                for values in [&mut target.positions, &mut target.normals] {
+                    if let Some(offset) = values.get(original as usize).copied() {
+                        values.push(offset);
+                    }
+                }
+            }
+            (mesh.verts.len() - 1) as u32
+        };
+        set(&mut mesh.verts[copy as usize], *value);
+        existing.push((*value, copy));
+        *i = copy;
+    }
+}
+
+/// Area weighted normal of each triangle, so its length is twice the triangle's area
+fn face_normals(verts: &[Vertex], inds: &[u32]) -> Vec<Vec3> {
+    inds.chunks_exact(3)
+        .map(|t| {
+            let [a, b, c] = [t[0], t[1], t[2]].map(|i| verts[i as usize].pos);
+            (b - a).cross(c - a)
+        })
+        .collect()
+}
+
+/// Replaces the normals of `mesh`'s vertices with ones worked out from the triangles of `inds`.
+/// Corners in the same place are smoothed together even if they're different vertices, e.g. on
+/// either side of a seam in the texture coordinates.
+pub fn generate_normals(mesh: &mut Mesh, inds: &mut [u32], mode: NormalMode) {
+    let faces = face_normals(&mesh.verts, inds);
+    let corner_normals: Vec<Vec3> = match mode {
+        NormalMode::Flat => faces
+            .iter()
+            .flat_map(|n| [n.normalize_or_zero(); 3])
+            .collect(),
+        NormalMode::Smooth { crease_angle } => {
+            let min_cos = crease_angle.to_radians().cos();
+            // How much each corner adds to the normals around it, weighted by its triangle's
+            // area and its angle
+            let weighted: Vec<Vec3> = inds
+                .chunks_exact(3)
+                .zip(&faces)
+                .flat_map(|(t, face)| {
+                    let [a, b, c] = [t[0], t[1], t[2]].map(|i| mesh.verts[i as usize].pos);
+                    [(a, b, c), (b, c, a), (c, a, b)]
+                        .map(|(p, next, prev)| *face * (next - p).angle_between(prev - p))
+                })
+                .map(|n| if n.is_finite() { n } else { Vec3::ZERO })
+                .collect();
+
+            let mut by_position: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+            for (corner, i) in inds.iter().enumerate() {
+                let pos = mesh.verts[*i as usize].pos;
+                by_position
+                    .entry(pos.to_array().map(f32::to_bits))
+                    .or_default()
+                    .push(corner);
+            }
+
+            inds.iter()
+                .enumerate()
+                .map(|(corner, i)| {
+                    let face = faces[corner / 3].normalize_or_zero();
+                    let pos = mesh.verts[*i as usize].pos;
+                    let normal: Vec3 = by_position[&pos.to_array().map(f32::to_bits)]
+                        .iter()
+                        .filter(|other| faces[**other / 3].normalize_or_zero().dot(face) >= min_cos)
+                        .map(|other| weighted[*other])
+                        .sum();
+                    normal.try_normalize().unwrap_or(face)
+                })
+                .collect()
+        }
+    };
+
+    set_corners(mesh, inds, &corner_normals, |v, n| v.norm = n);
+}
+
+/// Replaces the texture coordinates of `mesh`'s vertices with ones projected from the positions
+/// of the triangles of `inds`. Does nothing for [`UvProjection::None`].
+pub fn project_uvs(mesh: &mut Mesh, inds: &mut [u32], projection: UvProjection) {
+    let Some(aabb) = Aabb::from_points(inds.iter().map(|i| mesh.verts[*i as usize].pos)) else {
+        return;
+    };
+    let size = (aabb.max - aabb.min).max(Vec3::splat(f32::EPSILON));
+    // Dropping `axis` from a position in the box, as 0 to 1 across the box
+    let project = |pos: Vec3, axis: usize| {
+        let relative = (pos - aabb.min) / size;
+        match axis {
+            0 => Vec2::new(relative.z, relative.y),
+            1 => Vec2::new(relative.x, relative.z),
+            _ => Vec2::new(relative.x, relative.y),
+        }
+    };
+
+    let corner_uvs: Vec<Vec2> = match projection {
+        UvProjection::None => return,
+        UvProjection::Planar => {
+            let axis = size.min_position();
+            inds.iter()
+                .map(|i| project(mesh.verts[*i as usize].pos, axis))
+                .collect()
+        }
+        UvProjection::Box => {
+            // Every side uses the same scale so the texture isn't stretched more on some than
+            // others
+            let scale = size / size.max_element();
+            let faces = face_normals(&mesh.verts, inds);
+            inds.iter()
+                .enumerate()
+                .map(|(corner, i)| {
+                    let axis = faces[corner / 3].abs().max_position();
+                    let pos = mesh.verts[*i as usize].pos;
+                    project(aabb.min + (pos - aabb.min) * scale, axis)
+                })
+                .collect()
+        }
+    };
+
+    set_corners(mesh, inds, &corner_uvs, |v, tc| v.tc = tc);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cube with 8 shared vertices, so generating normals has to split them
+    fn cube() -> (Mesh, Vec<u32>) {
+        let verts = (0..8)
+            .map(|i| Vertex {
+                pos: Vec3::new((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32),
+                ..Default::default()
+            })
+            .collect();
+        #[rustfmt::skip]
+        let inds = vec![
+            0, 2, 1, 1, 2, 3, // -z
+            4, 5, 6, 5, 7, 6, // +z
+            0, 1, 4, 1, 5, 4, // -y
+            2, 6, 3, 3, 6, 7, // +y
+            0, 4, 2, 2, 4, 6, // -x
+            1, 3, 5, 3, 7, 5, // +x
+        ];
+        let mesh = Mesh {
+            verts,
+            ..Default::default()
+        };
+        (mesh, inds)
+    }
+
+    #[test]
+    fn normals_split_at_creases() {
+        let (mut mesh, mut inds) = cube();
+        generate_normals(&mut mesh, &mut inds, NormalMode::default());
+        // Every corner of the cube is 3 vertices, one for each side
+        assert_eq!(mesh.verts.len(), 24);
+        for t in inds.chunks_exact(3) {
+            let [a, b, c] = [t[0], t[1], t[2]].map(|i| mesh.verts[i as usize]);
+            let face = (b.pos - a.pos).cross(c.pos - a.pos).normalize();
+            assert!([a, b, c].iter().all(|v| v.norm.abs_diff_eq(face, 1e-5)));
+        }
+
+        // Without the crease, each corner points out diagonally
+        let (mut mesh, mut inds) = cube();
+        generate_normals(
+            &mut mesh,
+            &mut inds,
+            NormalMode::Smooth {
+                crease_angle: 180.0,
+            },
+        );
+        assert_eq!(mesh.verts.len(), 8);
+        let center = Vec3::splat(0.5);
+        for v in &mesh.verts {
+            assert!(v.norm.abs_diff_eq((v.pos - center).normalize(), 1e-5));
+        }
+    }
+
+    #[test]
+    fn box_projection_uses_facing_side() {
+        let (mut mesh, mut inds) = cube();
+        project_uvs(&mut mesh, &mut inds, UvProjection::Box);
+        // Corners are only shared by sides that give them the same coordinates
+        assert!(mesh.verts.len() > 8);
+        for t in inds.chunks_exact(3) {
+            let [a, b, c] = [t[0], t[1], t[2]].map(|i| mesh.verts[i as usize]);
+            // Each side of the cube is covered by the whole texture
+            let uvs = [a.tc, b.tc, c.tc];
+            assert!(uvs
+                .iter()
+                .all(|uv| uv.cmpge(Vec2::ZERO).all() && uv.cmple(Vec2::ONE).all()));
+            assert!(uvs[0] != uvs[1] && uvs[1] != uvs[2] && uvs[0] != uvs[2]);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use glam::{Mat4, Quat, Vec3};
+
+use crate::{JointTransform, Skeleton};
+
+/// The rotation of a model space transform
+fn rotation(transform: &Mat4) -> Quat {
+    transform.to_scale_rotation_translation().1
+}
+
+/// Rotates the joint at `index` by `rot` in model space, given the joint's current model space
+/// rotation and its parent's
+fn rotate_joint(pose: &mut [JointTransform], index: usize, rot: Quat, global: Quat, parent: Quat) {
+    pose[index].rot = (parent.inverse() * rot * global).normalize();
+}
+
+/// Blends the rotations of `joints` in `pose` back towards `original` by `1 - weight`
+fn apply_weight(
+    pose: &mut [JointTransform],
+    original: &[JointTransform],
+    joints: &[usize],
+    weight: f32,
+) {
+    if weight >= 1.0 {
+        return;
+    }
+    for &j in joints {
+        pose[j].rot = original[j].rot.slerp(pose[j].rot, weight.max(0.0));
+    }
+}
+
+/// Checks each joint in `chain` is the parent of the next one
+fn is_chain(skeleton: &Skeleton, chain: &[usize]) -> bool {
+    chain.iter().all(|&j| j < skeleton.joints.len())
+        && chain
+            .windows(2)
+            .all(|pair| skeleton.joints[pair[1]].parent == Some(pair[0] as u8))
+}
+
+/// Analytic solver for a chain of three joints such as shoulder, elbow and wrist or hip, knee
+/// and ankle. Rotates the first two joints so the end joint reaches the target, with the middle
+/// joint bending towards the pole.
+#[derive(Clone, Debug)]
+pub struct TwoBoneIk {
+    pub root: usize,
+    pub mid: usize,
+    pub end: usize,
+    /// Where the end joint should be, in model space
+    pub target: Vec3,
+    /// A point in model space that the middle joint bends towards, e.g. in front of the knee
+    pub pole: Vec3,
+    /// How much the solution replaces the original pose, from 0 to 1
+    pub weight: f32,
+}
+
+impl TwoBoneIk {
+    /// Creates a solver for the given joint indices.
+    /// Returns None if the joints aren't a parent, child and grandchild.
+    pub fn new(skeleton: &Skeleton, root: usize, mid: usize, end: usize) -> Option<Self> {
+        if !is_chain(skeleton, &[root, mid, end]) {
+            return None;
+        }
+
+        Some(TwoBoneIk {
+            root,
+            mid,
+            end,
+            target: Vec3::ZERO,
+            pole: Vec3::ZERO,
+            weight: 1.0,
+        })
+    }
+
+    /// Creates a solver for the joints with the given names, see [`TwoBoneIk::new`]
+    pub fn from_names(skeleton: &Skeleton, root: &str, mid: &str, end: &str) -> Option<Self> {
+        Self::new(
+            skeleton,
+            skeleton.joint_index(root)?,
+            skeleton.joint_index(mid)?,
+            skeleton.joint_index(end)?,
+        )
+    }
+
+    /// Adjusts the rotations of the root and middle joints in `pose`.
+    /// If the target is out of reach the chain is straightened towards it.
+    pub fn solve(&self, skeleton: &Skeleton, pose: &mut [JointTransform]) {
+        let Some(globals) = skeleton.model_space_transforms(pose) else {
+            return;
+        };
+        let original = pose.to_vec();
+
+        let a = globals[self.root].w_axis.truncate();
+        let b = globals[self.mid].w_axis.truncate();
+        let c = globals[self.end].w_axis.truncate();
+        let root_rot = rotation(&globals[self.root]);
+        let mid_rot = rotation(&globals[self.mid]);
+        let parent_rot = skeleton.joints[self.root]
+            .parent
+            .map_or(Quat::IDENTITY, |p| rotation(&globals[p as usize]));
+
+        let upper_len = (b - a).length();
+        let lower_len = (c - b).length();
+        if upper_len <= f32::EPSILON || lower_len <= f32::EPSILON {
+            return;
+        }
+
+        // Keep slightly short of fully straight so the bend direction stays well defined
+        let eps = 1e-4;
+        let target_len = (self.target - a).length().clamp(
+            (upper_len - lower_len).abs() + eps,
+            upper_len + lower_len - eps,
+        );
+
+        // Bend the middle joint so the distance from root to end matches the target distance,
+        // using the law of cosines for the current and desired angle at the middle joint
+        let interior = |len: f32| {
+            ((upper_len * upper_len + lower_len * lower_len - len * len)
+                / (2.0 * upper_len * lower_len))
+                .clamp(-1.0, 1.0)
+                .acos()
+        };
+        let current_angle = (a - b).angle_between(c - b);
+        let desired_angle = interior(target_len);
+
+        let mut bend_axis = (c - b).cross(a - b);
+        if bend_axis.length_squared() < 1e-8 {
+            // Straight limb, so bend in the plane containing the pole. Which way it bends
+            // doesn't matter as the twist below moves the middle joint to the pole's side.
+            bend_axis = (c - a).cross(self.pole - a);
+        }
+        if bend_axis.length_squared() < 1e-8 {
+            bend_axis = (c - b).any_orthonormal_vector();
+        }
+        let bend = Quat::from_axis_angle(bend_axis.normalize(), current_angle - desired_angle);
+        rotate_joint(pose, self.mid, bend, mid_rot, root_rot);
+        let c = b + bend * (c - b);
+
+        // Aim the root so the end joint points at the target
+        let aim = Quat::from_rotation_arc((c - a).normalize(), (self.target - a).normalize());
+
+        // Twist around the root to target axis so the middle joint is on the side of the pole
+        let axis = (self.target - a).normalize();
+        let project = |v: Vec3| v - axis * v.dot(axis);
+        let mid_dir = project(aim * (b - a));
+        let pole_dir = project(self.pole - a);
+        let twist = if mid_dir.length_squared() > 1e-8 && pole_dir.length_squared() > 1e-8 {
+            Quat::from_rotation_arc(mid_dir.normalize(), pole_dir.normalize())
+        } else {
+            Quat::IDENTITY
+        };
+
+        rotate_joint(pose, self.root, twist * aim, root_rot, parent_rot);
+        apply_weight(pose, &original, &[self.root, self.mid], self.weight);
+    }
+}
+
+/// Iterative cyclic coordinate descent solver for a chain of any length, such as a tail or spine
+#[derive(Clone, Debug)]
+pub struct CcdIk {
+    /// Joint indices from the start of the chain to the end effector, each the parent of the next
+    pub chain: Vec<usize>,
+    /// Where the end effector should be, in model space
+    pub target: Vec3,
+    pub iterations: u32,
+    /// Stops iterating once the end effector is this close to the target
+    pub tolerance: f32,
+    /// How much the solution replaces the original pose, from 0 to 1
+    pub weight: f32,
+}
+
+impl CcdIk {
+    /// Creates a solver for the given chain of joint indices, from the start of the chain to the
+    /// end effector. Returns None if the chain has fewer than 2 joints or isn't connected.
+    pub fn new(skeleton: &Skeleton, chain: Vec<usize>) -> Option<Self> {
+        if chain.len() < 2 || !is_chain(skeleton, &chain) {
+            return None;
+        }
+
+        Some(CcdIk {
+            chain,
+            target: Vec3::ZERO,
+            iterations: 10,
+            tolerance: 0.001,
+            weight: 1.0,
+        })
+    }
+
+    /// Creates a solver for the joints with the given names, see [`CcdIk::new`]
+    pub fn from_names(skeleton: &Skeleton, chain: &[&str]) -> Option<Self> {
+        let chain = chain
+            .iter()
+            .map(|name| skeleton.joint_index(name))
+            .collect::<Option<_>>()?;
+        Self::new(skeleton, chain)
+    }
+
+    /// The model space transforms of each joint in the chain
+    fn chain_transforms(&self, parent: Mat4, pose: &[JointTransform]) -> Vec<Mat4> {
+        self.chain
+            .iter()
+            .scan(parent, |transform, &j| {
+                *transform *= pose[j].matrix();
+                Some(*transform)
+            })
+            .collect()
+    }
+
+    /// Adjusts the rotations of every joint in the chain except the end effector in `pose`
+    pub fn solve(&self, skeleton: &Skeleton, pose: &mut [JointTransform]) {
+        let Some(globals) = skeleton.model_space_transforms(pose) else {
+            return;
+        };
+        let original = pose.to_vec();
+        let parent = skeleton.joints[self.chain[0]]
+            .parent
+            .map_or(Mat4::IDENTITY, |p| globals[p as usize]);
+        let end = self.chain.len() - 1;
+
+        for _ in 0..self.iterations {
+            for k in (0..end).rev() {
+                let transforms = self.chain_transforms(parent, pose);
+                let joint_pos = transforms[k].w_axis.truncate();
+                let to_end = transforms[end].w_axis.truncate() - joint_pos;
+                let to_target = self.target - joint_pos;
+                if to_end.length_squared() < 1e-8 || to_target.length_squared() < 1e-8 {
+                    continue;
+                }
+
+                let rot = Quat::from_rotation_arc(to_end.normalize(), to_target.normalize());
+                let parent_rot = if k == 0 {
+                    rotation(&parent)
+                } else {
+                    rotation(&transforms[k - 1])
+                };
+                rotate_joint(
+                    pose,
+                    self.chain[k],
+                    rot,
+                    rotation(&transforms[k]),
+                    parent_rot,
+                );
+            }
+
+            let transforms = self.chain_transforms(parent, pose);
+            if transforms[end].w_axis.truncate().distance(self.target) <= self.tolerance {
+                break;
+            }
+        }
+
+        apply_weight(pose, &original, &self.chain, self.weight);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Joint;
+
+    /// A straight chain of joints pointing up the Y axis, each 1 unit apart
+    fn straight_chain(len: usize) -> Skeleton {
+        let joints = (0..len)
+            .map(|i| Joint {
+                index: i as u8,
+                name: format!("Joint{i}"),
+                base_transform: JointTransform {
+                    pos: if i == 0 { Vec3::ZERO } else { Vec3::Y },
+                    ..Default::default()
+                },
+                children: if i + 1 < len {
+                    vec![(i + 1) as u8]
+                } else {
+                    Vec::new()
+                },
+                parent: i.checked_sub(1).map(|p| p as u8),
+                ..Default::default()
+            })
+            .collect();
+        Skeleton { joints }
+    }
+
+    fn position(skeleton: &Skeleton, pose: &[JointTransform], joint: usize) -> Vec3 {
+        skeleton.model_space_transforms(pose).unwrap()[joint]
+            .w_axis
+            .truncate()
+    }
+
+    #[test]
+    fn two_bone_reaches_target_towards_pole() {
+        let skeleton = straight_chain(3);
+        let mut pose = skeleton.base_pose();
+
+        let mut ik = TwoBoneIk::from_names(&skeleton, "Joint0", "Joint1", "Joint2").unwrap();
+        ik.target = Vec3::new(1.0, 1.0, 0.0);
+        ik.pole = Vec3::new(0.0, 1.0, 5.0);
+        ik.solve(&skeleton, &mut pose);
+
+        assert!(position(&skeleton, &pose, 2).distance(ik.target) < 1e-3);
+        assert!(position(&skeleton, &pose, 1).z > 0.1);
+    }
+
+    #[test]
+    fn ccd_reaches_target() {
+        let skeleton = straight_chain(4);
+        let mut pose = skeleton.base_pose();
+
+        let mut ik = CcdIk::new(&skeleton, vec![0, 1, 2, 3]).unwrap();
+        ik.target = Vec3::new(1.5, 1.5, 0.5);
+        ik.iterations = 50;
+        ik.solve(&skeleton, &mut pose);
+
+        assert!(position(&skeleton, &pose, 3).distance(ik.target) < 0.01);
+    }
+
+    #[test]
+    fn rejects_disconnected_chain() {
+        let skeleton = straight_chain(3);
+        assert!(TwoBoneIk::new(&skeleton, 0, 2, 1).is_none());
+    }
+}
@@ -0,0 +1,181 @@
+//! Platform-neutral descriptions of how vertex types are laid out in memory, so the GPU's
+//! attribute loaders can be set up from the types themselves instead of by hand.
+
+use glam::{Vec2, Vec3, Vec4};
+
+/// The type of each component of an attribute
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComponentType {
+    Byte,
+    UnsignedByte,
+    Short,
+    Float,
+}
+
+impl ComponentType {
+    /// Size in bytes
+    pub const fn size(self) -> usize {
+        match self {
+            ComponentType::Byte | ComponentType::UnsignedByte => 1,
+            ComponentType::Short => 2,
+            ComponentType::Float => 4,
+        }
+    }
+}
+
+/// A field of a vertex that is given to the vertex shader
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Attribute {
+    /// Which input register (`v0`, `v1`, ...) the shader reads it from
+    pub register: u8,
+    pub component: ComponentType,
+    /// Number of components, from 1 to 4
+    pub count: u8,
+    /// Offset in bytes from the start of the vertex
+    pub offset: usize,
+}
+
+impl Attribute {
+    /// Size in bytes
+    pub const fn size(&self) -> usize {
+        self.component.size() * self.count as usize
+    }
+}
+
+/// A type that can be read by the GPU as an attribute
+pub trait AttributeType: bytemuck::Pod {
+    const COMPONENT: ComponentType;
+    const COUNT: u8;
+}
+
+macro_rules! attribute_types {
+    ($($ty:ty => $component:ident, $count:literal;)*) => {
+        $(impl AttributeType for $ty {
+            const COMPONENT: ComponentType = ComponentType::$component;
+            const COUNT: u8 = $count;
+        })*
+    };
+}
+
+attribute_types! {
+    f32 => Float, 1;
+    Vec2 => Float, 2;
+    Vec3 => Float, 3;
+    Vec4 => Float, 4;
+    [i8; 2] => Byte, 2;
+    [i8; 3] => Byte, 3;
+    [i8; 4] => Byte, 4;
+    [u8; 2] => UnsignedByte, 2;
+    [u8; 3] => UnsignedByte, 3;
+    [u8; 4] => UnsignedByte, 4;
+    [i16; 2] => Short, 2;
+    [i16; 3] => Short, 3;
+    [i16; 4] => Short, 4;
+}
+
+/// Used by [`vertex_layout`] to get the attribute type of a field
+#[doc(hidden)]
+pub const fn attribute_of<V, T: AttributeType>(
+    register: u8,
+    offset: usize,
+    _field: fn(&V) -> &T,
+) -> Attribute {
+    Attribute {
+        register,
+        component: T::COMPONENT,
+        count: T::COUNT,
+        offset,
+    }
+}
+
+/// A vertex type whose attributes are described by [`vertex_layout`]
+pub trait VertexLayout: Sized {
+    /// In the order they're laid out in memory
+    const ATTRIBUTES: &'static [Attribute];
+    /// Size in bytes of each vertex
+    const STRIDE: usize = size_of::<Self>();
+
+    /// Copies each attribute into `out` at its offset, leaving any padding untouched
+    fn write_bytes(&self, out: &mut [u8]);
+}
+
+/// The vertices as laid out in memory, with any padding zeroed
+pub fn vertex_bytes<V: VertexLayout>(verts: &[V]) -> Vec<u8> {
+    let mut out = vec![0; verts.len() * V::STRIDE];
+    for (v, out) in verts.iter().zip(out.chunks_exact_mut(V::STRIDE)) {
+        v.write_bytes(out);
+    }
+    out
+}
+
+/// Where the GPU will read each attribute from, which is only given the attribute types and the
+/// stride. It places attributes one after the other, each aligned to the size of its components.
+pub fn gpu_offsets(attributes: &[Attribute]) -> Vec<usize> {
+    let mut offset = 0usize;
+    attributes
+        .iter()
+        .map(|a| {
+            let aligned = offset.next_multiple_of(a.component.size());
+            offset = aligned + a.size();
+            aligned
+        })
+        .collect()
+}
+
+/// Implements [`VertexLayout`] for a `#[repr(C)]` struct, taking the component type and count of
+/// each attribute from the type of its field. Fields must be listed in the order they're
+/// declared.
+///
+/// ```ignore
+/// vertex_layout!(Vertex { pos => 0, norm => 1, tc => 2 });
+/// ```
+#[macro_export]
+macro_rules! vertex_layout {
+    ($ty:ty { $($field:ident => $register:literal),* $(,)? }) => {
+        impl $crate::layout::VertexLayout for $ty {
+            const ATTRIBUTES: &'static [$crate::layout::Attribute] = &[$(
+                $crate::layout::attribute_of(
+                    $register,
+                    ::std::mem::offset_of!($ty, $field),
+                    |v: &$ty| &v.$field,
+                )
+            ),*];
+
+            fn write_bytes(&self, out: &mut [u8]) {
+                $({
+                    let offset = ::std::mem::offset_of!($ty, $field);
+                    let bytes = $crate::bytemuck::bytes_of(&self.$field);
+                    out[offset..offset + bytes.len()].copy_from_slice(bytes);
+                })*
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{packed::PackedVertex, Vertex};
+
+    fn check_layout<V: VertexLayout>() {
+        let attributes = V::ATTRIBUTES;
+        assert_eq!(
+            attributes.iter().map(|a| a.offset).collect::<Vec<_>>(),
+            gpu_offsets(attributes),
+            "GPU would read attributes from the wrong place"
+        );
+
+        let last = attributes.last().unwrap();
+        assert!(last.offset + last.size() <= V::STRIDE);
+        assert!(
+            V::STRIDE - (last.offset + last.size()) < 4,
+            "Attributes are missing from the end"
+        );
+    }
+
+    #[test]
+    fn offsets_match_repr_c() {
+        check_layout::<Vertex>();
+        check_layout::<PackedVertex>();
+    }
+}
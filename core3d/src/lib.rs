@@ -1,6 +1,29 @@
-use glam::{Mat4, Quat, Vec2, Vec3};
+#[doc(hidden)]
+pub use bytemuck;
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
 use serde::{Deserialize, Serialize};
 
+pub mod bounds;
+pub mod container;
+pub mod generate;
+pub mod ik;
+pub mod layout;
+pub mod look_at;
+pub mod mask;
+pub mod material;
+pub mod mixer;
+pub mod morph;
+pub mod optimize;
+pub mod packed;
+pub mod partition;
+pub mod player;
+pub mod ray;
+pub mod retarget;
+pub mod root_motion;
+pub mod scene;
+pub mod validation;
+pub mod weld;
+
 #[repr(C)]
 #[derive(Clone, Copy, Serialize, Deserialize, Debug)]
 pub struct Vertex {
@@ -25,11 +48,57 @@ impl Default for Vertex {
     }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+impl Vertex {
+    /// Where the vertex shader puts the vertex when skinned by `joints`, as returned by
+    /// [`Skeleton::apply_pose_to_joints`]
+    pub fn skinned_pos(&self, joints: &[Mat4]) -> Vec3 {
+        self.joints
+            .iter()
+            .zip(self.weights.to_array())
+            .filter(|(_, weight)| *weight != 0.0)
+            .map(|(joint, weight)| joints[*joint as usize].transform_point3(self.pos) * weight)
+            .sum()
+    }
+}
+
+// SAFETY: Every field is made of `f32`s and `u8`s, which are valid for any bit pattern, so a
+// vertex can be read straight out of a model file. It isn't `Pod` because of the padding byte
+// after `joints`.
+unsafe impl bytemuck::Zeroable for Vertex {}
+unsafe impl bytemuck::AnyBitPattern for Vertex {}
+
+vertex_layout!(Vertex {
+    pos => 0,
+    norm => 1,
+    tc => 2,
+    joints => 3,
+    weights => 4,
+});
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct Mesh {
     pub verts: Vec<Vertex>,
     pub inds: Vec<u16>,
-    pub texture: u8,
+    /// Index into [`Model::materials`]
+    pub material: u8,
+    /// How the vertices are stored in the model file and given to the GPU
+    #[serde(default)]
+    pub format: packed::VertexFormat,
+    /// Calculated from the vertices and morph targets, see [`bounds::MeshBounds::from_mesh`]
+    #[serde(default)]
+    pub bounds: bounds::MeshBounds,
+    #[serde(default)]
+    pub morph_targets: Vec<morph::MorphTarget>,
+    /// The weight of each morph target when it isn't animated
+    #[serde(default)]
+    pub morph_weights: Vec<f32>,
+    /// The skeleton joint for each joint index used by the vertices, or empty if they're already
+    /// skeleton joints. See [`partition`].
+    #[serde(default)]
+    pub palette: Vec<u8>,
+    /// How the indices make triangles
+    #[serde(default)]
+    pub topology: optimize::Topology,
 }
 
 #[derive(Clone, Copy, Serialize, Deserialize, Debug)]
@@ -43,11 +112,30 @@ impl JointTransform {
     pub fn matrix(&self) -> Mat4 {
         Mat4::from_scale_rotation_translation(self.scale, self.rot, self.pos)
     }
+
+    /// Blends towards `other` by `s`, where 0 is entirely `self` and 1 is entirely `other`
+    pub fn blend(&self, other: &JointTransform, s: f32) -> JointTransform {
+        JointTransform {
+            pos: self.pos.lerp(other.pos, s),
+            rot: self.rot.slerp(other.rot, s),
+            scale: self.scale.lerp(other.scale, s),
+        }
+    }
+
+    /// Applies an additive `delta` (from an additive animation) on top of this transform,
+    /// scaled by `weight`
+    pub fn add(&self, delta: &JointTransform, weight: f32) -> JointTransform {
+        JointTransform {
+            pos: self.pos + delta.pos * weight,
+            rot: self.rot * Quat::IDENTITY.slerp(delta.rot, weight),
+            scale: self.scale * Vec3::ONE.lerp(delta.scale, weight),
+        }
+    }
 }
 
-impl Into<Mat4> for JointTransform {
-    fn into(self) -> Mat4 {
-        self.matrix()
+impl From<JointTransform> for Mat4 {
+    fn from(transform: JointTransform) -> Self {
+        transform.matrix()
     }
 }
 
@@ -56,7 +144,7 @@ impl Default for JointTransform {
         JointTransform {
             pos: Vec3::ZERO,
             rot: Quat::IDENTITY,
-            scale: Vec3::ZERO,
+            scale: Vec3::ONE,
         }
     }
 }
@@ -78,11 +166,7 @@ impl Default for Joint {
         Self {
             index: 0,
             name: String::new(),
-            base_transform: JointTransform {
-                pos: Vec3::ZERO,
-                rot: Quat::IDENTITY,
-                scale: Vec3::ONE,
-            },
+            base_transform: JointTransform::default(),
             inverse_bind_matrix: Mat4::IDENTITY,
             children: Vec::new(),
             parent: None,
@@ -101,14 +185,24 @@ impl Skeleton {
     pub fn base_pose(&self) -> Vec<JointTransform> {
         self.joints.iter().map(|j| j.base_transform).collect()
     }
+
+    /// Finds the index of the joint with the given name
+    pub fn joint_index(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|j| j.name == name)
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub textures: Vec<Texture>,
+    #[serde(default)]
+    pub materials: Vec<material::Material>,
     pub skeleton: Skeleton,
     pub animations: Vec<Animation>,
+    /// Where each mesh goes, and how the meshes and joints are related
+    #[serde(default)]
+    pub scene: scene::Scene,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -118,111 +212,331 @@ pub struct Texture {
     pub height: u16,
 }
 
+/// How values are interpolated between the keyframes of a track
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Holds the value of the previous keyframe until the next one
+    Step,
+    /// Linear interpolation for translations and scales, spherical linear for rotations
+    #[default]
+    Linear,
+    /// Hermite cubic spline using the in and out tangents of each keyframe
+    CubicSpline,
+}
+
+/// A sequence of keyframes for a single property of a joint
 #[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Track<T> {
+    pub interpolation: Interpolation,
+    /// Keyframe times and values, sorted by time
+    pub keys: Vec<(f32, T)>,
+    /// In and out tangents for each keyframe, only used for cubic spline interpolation
+    pub tangents: Vec<(T, T)>,
+}
+
+impl<T> Default for Track<T> {
+    fn default() -> Self {
+        Track {
+            interpolation: Interpolation::default(),
+            keys: Vec::new(),
+            tangents: Vec::new(),
+        }
+    }
+}
+
+impl<T: Interpolate> Track<T> {
+    /// Creates a track with the given interpolation and keyframes, without any tangents
+    pub fn new(interpolation: Interpolation, keys: Vec<(f32, T)>) -> Self {
+        Track {
+            interpolation,
+            keys,
+            tangents: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Transforms every keyframe value with `value`, and every tangent with `tangent`
+    pub fn map_values(&mut self, value: impl Fn(T) -> T, tangent: impl Fn(T) -> T) {
+        for (_, v) in &mut self.keys {
+            *v = value(*v);
+        }
+        for (in_tangent, out_tangent) in &mut self.tangents {
+            *in_tangent = tangent(*in_tangent);
+            *out_tangent = tangent(*out_tangent);
+        }
+    }
+
+    /// Samples the track at time `t`, holding the first or last keyframe outside the track.
+    /// Returns None if the track has no keyframes.
+    pub fn sample(&self, t: f32) -> Option<T> {
+        let (before, after, s) = bracket(&self.keys, t)?;
+        let (t0, v0) = self.keys[before];
+        let (t1, v1) = self.keys[after];
+
+        Some(match self.interpolation {
+            Interpolation::Step => v0,
+            Interpolation::Linear => v0.interpolate(v1, s),
+            Interpolation::CubicSpline => {
+                match (self.tangents.get(before), self.tangents.get(after)) {
+                    (Some((_, out_tangent)), Some((in_tangent, _))) => {
+                        v0.hermite(*out_tangent, v1, *in_tangent, s, t1 - t0)
+                    }
+                    // Missing tangents, fall back to linear rather than misusing other keys' tangents
+                    _ => v0.interpolate(v1, s),
+                }
+            }
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct JointAnimation {
-    pub translations: Vec<(f32, Vec3)>,
-    pub rotations: Vec<(f32, Quat)>,
-    pub scales: Vec<(f32, Vec3)>,
+    pub translations: Track<Vec3>,
+    pub rotations: Track<Quat>,
+    pub scales: Track<Vec3>,
 }
 
 impl JointAnimation {
-    pub fn sample(&self, t: f32) -> JointTransform {
-        let mut out = JointTransform::default();
+    /// Samples this joint's transform at time `t`, using the corresponding part of `base` for any
+    /// track that has no keyframes
+    pub fn sample(&self, t: f32, base: &JointTransform) -> JointTransform {
+        JointTransform {
+            pos: self.translations.sample(t).unwrap_or(base.pos),
+            rot: self.rotations.sample(t).unwrap_or(base.rot),
+            scale: self.scales.sample(t).unwrap_or(base.scale),
+        }
+    }
+}
 
-        // Translation
-        let t_tran = t % self.translations.last().map(|(len, _)| *len).unwrap_or(1.0);
-        let (t_before, tran_before) = self
-            .translations
-            .iter()
-            .filter(|(t, _)| *t <= t_tran)
-            .next()
-            .copied()
-            .unwrap_or((0.0, Vec3::ZERO));
-        let (t_after, tran_after) = self
-            .translations
-            .iter()
-            .filter(|(t, _)| *t > t_tran)
-            .next()
-            .copied()
-            .unwrap_or((1.0, Vec3::ZERO));
-
-        let t_interp = (t_tran - t_before) / (t_after - t_before);
-        out.pos = tran_before.lerp(tran_after, t_interp);
-
-        // Rotation
-        let t_rot = t % self.rotations.last().map(|(len, _)| *len).unwrap_or(1.0);
-        let (t_before, rot_before) = self
-            .rotations
-            .iter()
-            .filter(|(t, _)| *t <= t_rot)
-            .next()
-            .copied()
-            .unwrap_or((0.0, Quat::IDENTITY));
-        let (t_after, rot_after) = self
-            .rotations
-            .iter()
-            .filter(|(t, _)| *t > t_rot)
-            .next()
-            .copied()
-            .unwrap_or((1.0, Quat::IDENTITY));
-
-        let t_interp = (t_rot - t_before) / (t_after - t_before);
-        out.rot = rot_before.lerp(rot_after, t_interp);
-
-        // Translation
-        let t_scale = t % self.scales.last().map(|(len, _)| *len).unwrap_or(1.0);
-        let (t_before, scale_before) = self
-            .scales
-            .iter()
-            .filter(|(t, _)| *t <= t_scale)
-            .next()
-            .copied()
-            .unwrap_or((0.0, Vec3::ZERO));
-        let (t_after, scale_after) = self
-            .scales
-            .iter()
-            .filter(|(t, _)| *t > t_scale)
-            .next()
-            .copied()
-            .unwrap_or((1.0, Vec3::ZERO));
+/// A value that can be interpolated between keyframes
+pub trait Interpolate: Copy {
+    fn interpolate(self, other: Self, s: f32) -> Self;
 
-        let t_interp = (t_scale - t_before) / (t_after - t_before);
-        out.scale = scale_before.lerp(scale_after, t_interp);
+    /// Evaluates a cubic Hermite spline between `self` and `other` as described in the glTF
+    /// specification, where `span` is the time between the two keyframes
+    fn hermite(self, out_tangent: Self, other: Self, in_tangent: Self, s: f32, span: f32) -> Self;
+}
+
+/// Hermite basis functions for `s` in the range [0, 1]
+fn hermite_basis(s: f32) -> [f32; 4] {
+    let s2 = s * s;
+    let s3 = s2 * s;
+    [
+        2.0 * s3 - 3.0 * s2 + 1.0,
+        s3 - 2.0 * s2 + s,
+        -2.0 * s3 + 3.0 * s2,
+        s3 - s2,
+    ]
+}
 
-        out
+impl Interpolate for f32 {
+    fn interpolate(self, other: Self, s: f32) -> Self {
+        self + (other - self) * s
+    }
+
+    fn hermite(self, out_tangent: Self, other: Self, in_tangent: Self, s: f32, span: f32) -> Self {
+        let [h00, h10, h01, h11] = hermite_basis(s);
+        h00 * self + h10 * span * out_tangent + h01 * other + h11 * span * in_tangent
     }
 }
 
-impl Default for JointAnimation {
-    fn default() -> Self {
-        JointAnimation {
-            translations: Vec::new(),
-            rotations: Vec::new(),
-            scales: Vec::new(),
-        }
+impl Interpolate for Vec3 {
+    fn interpolate(self, other: Self, s: f32) -> Self {
+        self.lerp(other, s)
+    }
+
+    fn hermite(self, out_tangent: Self, other: Self, in_tangent: Self, s: f32, span: f32) -> Self {
+        let [h00, h10, h01, h11] = hermite_basis(s);
+        h00 * self + h10 * span * out_tangent + h01 * other + h11 * span * in_tangent
     }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+impl Interpolate for Quat {
+    fn interpolate(self, other: Self, s: f32) -> Self {
+        self.slerp(other, s)
+    }
+
+    fn hermite(self, out_tangent: Self, other: Self, in_tangent: Self, s: f32, span: f32) -> Self {
+        let [h00, h10, h01, h11] = hermite_basis(s);
+        let v = h00 * Vec4::from(self)
+            + h10 * span * Vec4::from(out_tangent)
+            + h01 * Vec4::from(other)
+            + h11 * span * Vec4::from(in_tangent);
+        Quat::from_vec4(v).normalize()
+    }
+}
+
+/// Finds the indices of the keyframes either side of `t` in a track sorted by time, and how far
+/// `t` is between them. Times outside the track are clamped to the first or last keyframe.
+fn bracket<T>(keys: &[(f32, T)], t: f32) -> Option<(usize, usize, f32)> {
+    if keys.is_empty() {
+        return None;
+    }
+
+    // Index of the first key after `t`, so the key before it is the last one at or before `t`
+    let after = keys.partition_point(|(key_t, _)| *key_t <= t);
+    if after == 0 {
+        return Some((0, 0, 0.0));
+    }
+    if after == keys.len() {
+        return Some((after - 1, after - 1, 0.0));
+    }
+
+    let before = after - 1;
+    let span = keys[after].0 - keys[before].0;
+    let s = if span > 0.0 {
+        (t - keys[before].0) / span
+    } else {
+        0.0
+    };
+
+    Some((before, after, s))
+}
+
+/// A named point in time in an animation, such as when a foot hits the ground
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Marker {
+    pub name: String,
+    pub time: f32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct Animation {
     pub name: String,
     pub joints: Vec<JointAnimation>,
+    /// Whether the keyframes are stored as differences from a reference pose,
+    /// see [`Animation::to_additive`]
+    pub additive: bool,
+    /// Sorted by time
+    pub markers: Vec<Marker>,
+    /// Movement taken out of the root joint, see [`Animation::extract_root_motion`]
+    pub root_motion: Option<root_motion::RootMotion>,
+    /// Weights for the morph targets of the scene's meshes
+    #[serde(default)]
+    pub morph_weights: Vec<morph::MorphAnimation>,
 }
 
 impl Animation {
-    pub fn sample(&self, t: f32) -> Vec<JointTransform> {
+    /// The time of the last keyframe across all tracks
+    pub fn duration(&self) -> f32 {
         self.joints
             .iter()
-            .map(|joint_anim| joint_anim.sample(t))
+            .flat_map(|j| {
+                [
+                    j.translations.keys.last().map(|k| k.0),
+                    j.rotations.keys.last().map(|k| k.0),
+                    j.scales.keys.last().map(|k| k.0),
+                ]
+            })
+            .flatten()
+            .chain(self.markers.iter().map(|m| m.time))
+            .fold(0.0, f32::max)
+    }
+
+    /// Finds every marker passed while playing the animation on a loop from time `from` to `to`,
+    /// in the order they were passed. Both times may be beyond the duration of the animation, so
+    /// markers are reported again each time the animation loops. If `to` is before `from` the
+    /// animation is treated as playing in reverse.
+    ///
+    /// The range includes `from` and excludes `to`, so consecutive calls won't report the same
+    /// marker twice.
+    pub fn crossed_markers(&self, from: f32, to: f32) -> Vec<&Marker> {
+        let duration = self.duration();
+        let mut crossed = Vec::new();
+        if self.markers.is_empty() || duration <= 0.0 || from == to {
+            return crossed;
+        }
+
+        let (start, end) = (from.min(to), from.max(to));
+        let first_loop = (start / duration).floor() as i64;
+        let last_loop = (end / duration).floor() as i64;
+
+        for repeat in first_loop..=last_loop {
+            let offset = repeat as f32 * duration;
+            for marker in &self.markers {
+                let t = marker.time + offset;
+                let in_range = if from < to {
+                    start <= t && t < end
+                } else {
+                    start < t && t <= end
+                };
+                if in_range {
+                    crossed.push(marker);
+                }
+            }
+        }
+
+        if to < from {
+            crossed.reverse();
+        }
+
+        crossed
+    }
+
+    /// Samples every joint at time `t`, which isn't wrapped to the duration of the animation,
+    /// see [`player::AnimationPlayer`] for looping. Joints without keyframes for a property keep
+    /// that property from the skeleton's base pose, or no change at all for additive animations.
+    pub fn sample(&self, t: f32, skeleton: &Skeleton) -> Vec<JointTransform> {
+        self.joints
+            .iter()
+            .zip(&skeleton.joints)
+            .map(|(joint_anim, joint)| {
+                if self.additive {
+                    joint_anim.sample(t, &JointTransform::default())
+                } else {
+                    joint_anim.sample(t, &joint.base_transform)
+                }
+            })
             .collect()
     }
+
+    /// Creates an additive version of this animation, where every keyframe is stored as the
+    /// difference from the matching joint in `reference` (usually the skeleton's base pose or
+    /// the first frame of another animation). The result can be layered on top of other
+    /// animations with [`JointTransform::add`].
+    pub fn to_additive(&self, reference: &[JointTransform]) -> Animation {
+        let joints = self
+            .joints
+            .iter()
+            .zip(reference)
+            .map(|(joint, reference)| {
+                let inv_rot = reference.rot.inverse();
+                let inv_scale = reference.scale.recip();
+
+                let mut joint = joint.clone();
+                joint
+                    .translations
+                    .map_values(|v| v - reference.pos, |tangent| tangent);
+                joint
+                    .rotations
+                    .map_values(|v| inv_rot * v, |tangent| inv_rot * tangent);
+                joint
+                    .scales
+                    .map_values(|v| v * inv_scale, |tangent| tangent * inv_scale);
+                joint
+            })
+            .collect();
+
+        Animation {
+            name: self.name.clone(),
+            joints,
+            additive: true,
+            markers: self.markers.clone(),
+            root_motion: None,
+            // Weights aren't relative to anything, so they stay as they are
+            morph_weights: self.morph_weights.clone(),
+        }
+    }
 }
 
 impl Skeleton {
-    /// Calculates the transformation matrices for a set of joint transforms.
+    /// Calculates the model space transform of each joint for a set of joint transforms.
     /// Will return None if the number of joint transforms provided does not match
     /// the number of joints in the skeleton.
-    pub fn apply_pose_to_joints(&self, pose: &[JointTransform]) -> Option<Vec<Mat4>> {
+    pub fn model_space_transforms(&self, pose: &[JointTransform]) -> Option<Vec<Mat4>> {
         if pose.len() != self.joints.len() {
             return None;
         }
@@ -251,7 +565,19 @@ impl Skeleton {
                 parent_transforms[*c as usize] = Some(current_transform);
             }
 
-            transforms[i] = current_transform * joint.inverse_bind_matrix;
+            transforms[i] = current_transform;
+        }
+
+        Some(transforms)
+    }
+
+    /// Calculates the transformation matrices for a set of joint transforms.
+    /// Will return None if the number of joint transforms provided does not match
+    /// the number of joints in the skeleton.
+    pub fn apply_pose_to_joints(&self, pose: &[JointTransform]) -> Option<Vec<Mat4>> {
+        let mut transforms = self.model_space_transforms(pose)?;
+        for (transform, joint) in transforms.iter_mut().zip(&self.joints) {
+            *transform *= joint.inverse_bind_matrix;
         }
 
         Some(transforms)
@@ -266,4 +592,99 @@ mod tests {
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn sample_uses_last_key_before_t() {
+        let anim = JointAnimation {
+            translations: Track::new(
+                Interpolation::Linear,
+                vec![
+                    (0.0, Vec3::ZERO),
+                    (1.0, Vec3::X),
+                    (2.0, Vec3::Y),
+                    (3.0, Vec3::Z),
+                ],
+            ),
+            ..Default::default()
+        };
+
+        let out = anim.sample(1.5, &JointTransform::default());
+        assert!(out.pos.abs_diff_eq(Vec3::new(0.5, 0.5, 0.0), 1e-6));
+    }
+
+    #[test]
+    fn sample_falls_back_to_base_transform() {
+        let anim = JointAnimation {
+            rotations: Track::new(
+                Interpolation::Linear,
+                vec![(0.0, Quat::IDENTITY), (1.0, Quat::from_rotation_y(1.0))],
+            ),
+            ..Default::default()
+        };
+        let base = JointTransform {
+            pos: Vec3::new(1.0, 2.0, 3.0),
+            rot: Quat::IDENTITY,
+            scale: Vec3::splat(2.0),
+        };
+
+        let out = anim.sample(0.5, &base);
+        assert_eq!(out.pos, base.pos);
+        assert_eq!(out.scale, base.scale);
+        assert!(out.rot.abs_diff_eq(Quat::from_rotation_y(0.5), 1e-5));
+    }
+
+    #[test]
+    fn step_holds_previous_key() {
+        let track = Track::new(
+            Interpolation::Step,
+            vec![(0.0, Vec3::ZERO), (1.0, Vec3::ONE), (2.0, Vec3::ZERO)],
+        );
+
+        assert_eq!(track.sample(0.99), Some(Vec3::ZERO));
+        assert_eq!(track.sample(1.5), Some(Vec3::ONE));
+    }
+
+    #[test]
+    fn cubic_spline_passes_through_keys() {
+        let track = Track {
+            interpolation: Interpolation::CubicSpline,
+            keys: vec![(0.0, Vec3::ZERO), (1.0, Vec3::X), (2.0, Vec3::ZERO)],
+            tangents: vec![(Vec3::NEG_Y, Vec3::Y); 3],
+        };
+
+        assert!(track.sample(1.0).unwrap().abs_diff_eq(Vec3::X, 1e-6));
+        // Leaves the first key moving up and arrives at the next moving down, so it should bulge up
+        assert!(track.sample(0.5).unwrap().y > 0.0);
+    }
+
+    #[test]
+    fn crossed_markers_wrap_around_loop() {
+        let anim = Animation {
+            joints: vec![JointAnimation {
+                translations: Track::new(
+                    Interpolation::Linear,
+                    vec![(0.0, Vec3::ZERO), (1.0, Vec3::X)],
+                ),
+                ..Default::default()
+            }],
+            markers: vec![
+                Marker {
+                    name: "left".to_string(),
+                    time: 0.25,
+                },
+                Marker {
+                    name: "right".to_string(),
+                    time: 0.75,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let names =
+            |markers: Vec<&Marker>| markers.iter().map(|m| m.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(anim.crossed_markers(0.0, 0.5)), ["left"]);
+        assert_eq!(names(anim.crossed_markers(0.5, 1.5)), ["right", "left"]);
+        assert_eq!(names(anim.crossed_markers(1.5, 0.5)), ["left", "right"]);
+        assert!(anim.crossed_markers(0.3, 0.7).is_empty());
+    }
 }
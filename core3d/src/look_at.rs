@@ -0,0 +1,191 @@
+use glam::{Mat4, Quat, Vec3};
+
+use crate::{JointTransform, Skeleton};
+
+/// Turns a joint such as the head so its forward axis points at a target, optionally spreading
+/// the turn over several joints such as the spine and neck so it looks more natural.
+#[derive(Clone, Debug)]
+pub struct LookAt {
+    /// Joints to rotate and how much of the turn each one takes, ordered from closest to the root
+    /// to the joint that should face the target, which must be last
+    pub joints: Vec<(usize, f32)>,
+    /// The direction the last joint faces, in its local space
+    pub forward: Vec3,
+    /// Furthest the joint can turn left or right from its animated direction, in radians
+    pub max_yaw: f32,
+    /// Furthest the joint can turn up or down from its animated direction, in radians
+    pub max_pitch: f32,
+    /// How quickly the joints turn to face the target, higher is faster. 0 turns instantly.
+    pub smoothing: f32,
+    /// How much the turn is applied, from 0 to 1
+    pub weight: f32,
+    /// The smoothed turn in model space
+    current: Quat,
+}
+
+impl LookAt {
+    /// Creates a constraint for the given joint indices and the share of the turn each takes.
+    /// Returns None if there are no joints or any are out of range.
+    pub fn new(skeleton: &Skeleton, joints: Vec<(usize, f32)>) -> Option<Self> {
+        if joints.is_empty() || joints.iter().any(|(j, _)| *j >= skeleton.joints.len()) {
+            return None;
+        }
+
+        Some(LookAt {
+            joints,
+            forward: Vec3::Z,
+            max_yaw: 70.0_f32.to_radians(),
+            max_pitch: 40.0_f32.to_radians(),
+            smoothing: 0.0,
+            weight: 1.0,
+            current: Quat::IDENTITY,
+        })
+    }
+
+    /// Creates a constraint for the joints with the given names, see [`LookAt::new`]
+    pub fn from_names(skeleton: &Skeleton, joints: &[(&str, f32)]) -> Option<Self> {
+        let joints = joints
+            .iter()
+            .map(|(name, share)| Some((skeleton.joint_index(name)?, *share)))
+            .collect::<Option<_>>()?;
+        Self::new(skeleton, joints)
+    }
+
+    /// Forgets any smoothing so the next update turns straight to the target
+    pub fn reset(&mut self) {
+        self.current = Quat::IDENTITY;
+    }
+
+    /// The turn in model space that points `forward` at `target_dir`, within the angle limits
+    fn limited_turn(&self, forward: Vec3, target_dir: Vec3) -> Quat {
+        let yaw_of = |v: Vec3| v.x.atan2(v.z);
+        let pitch_of = |v: Vec3| v.y.clamp(-1.0, 1.0).asin();
+
+        let mut yaw = yaw_of(target_dir) - yaw_of(forward);
+        // Turn whichever way round is shorter
+        if yaw > std::f32::consts::PI {
+            yaw -= std::f32::consts::TAU;
+        } else if yaw < -std::f32::consts::PI {
+            yaw += std::f32::consts::TAU;
+        }
+        let yaw = yaw.clamp(-self.max_yaw, self.max_yaw);
+        let pitch =
+            (pitch_of(target_dir) - pitch_of(forward)).clamp(-self.max_pitch, self.max_pitch);
+
+        let horizontal = Vec3::new(forward.x, 0.0, forward.z);
+        let pitch_rot = if horizontal.length_squared() > 1e-8 {
+            Quat::from_axis_angle(horizontal.cross(Vec3::Y).normalize(), pitch)
+        } else {
+            Quat::IDENTITY
+        };
+
+        Quat::from_rotation_y(yaw) * pitch_rot
+    }
+
+    /// Rotates the joints in `pose` towards `target`, a position in world space.
+    /// `model_matrix` is the model's transform in the world, and `dt` is the time since the last
+    /// update in seconds, used for smoothing.
+    pub fn apply(
+        &mut self,
+        skeleton: &Skeleton,
+        pose: &mut [JointTransform],
+        target: Vec3,
+        model_matrix: Mat4,
+        dt: f32,
+    ) {
+        let Some(globals) = skeleton.model_space_transforms(pose) else {
+            return;
+        };
+        let &(aim_joint, _) = self.joints.last().expect("Look at has no joints");
+
+        let (_, aim_rot, aim_pos) = globals[aim_joint].to_scale_rotation_translation();
+        let target = model_matrix.inverse().transform_point3(target);
+        let forward = (aim_rot * self.forward).normalize_or_zero();
+        let target_dir = (target - aim_pos).normalize_or_zero();
+        if forward == Vec3::ZERO || target_dir == Vec3::ZERO {
+            return;
+        }
+
+        let turn = self.limited_turn(forward, target_dir);
+        self.current = if self.smoothing > 0.0 {
+            self.current.slerp(turn, 1.0 - (-self.smoothing * dt).exp())
+        } else {
+            turn
+        };
+        let turn = Quat::IDENTITY.slerp(self.current, self.weight.clamp(0.0, 1.0));
+
+        // Split the turn between the joints, starting closest to the root so each joint's share
+        // is added on top of the ones before it
+        let total_share: f32 = self.joints.iter().map(|(_, share)| share).sum();
+        if total_share <= 0.0 {
+            return;
+        }
+        for &(joint, share) in &self.joints {
+            let Some(globals) = skeleton.model_space_transforms(pose) else {
+                return;
+            };
+            let global_rot = globals[joint].to_scale_rotation_translation().1;
+            let parent_rot = skeleton.joints[joint].parent.map_or(Quat::IDENTITY, |p| {
+                globals[p as usize].to_scale_rotation_translation().1
+            });
+
+            let partial = Quat::IDENTITY.slerp(turn, share / total_share);
+            pose[joint].rot = (parent_rot.inverse() * partial * global_rot).normalize();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Joint;
+
+    fn head_skeleton() -> Skeleton {
+        Skeleton {
+            joints: vec![Joint {
+                name: "Head".to_string(),
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn facing(skeleton: &Skeleton, pose: &[JointTransform]) -> Vec3 {
+        skeleton.model_space_transforms(pose).unwrap()[0].transform_vector3(Vec3::Z)
+    }
+
+    #[test]
+    fn turns_towards_target_within_limits() {
+        let skeleton = head_skeleton();
+        let mut look_at = LookAt::from_names(&skeleton, &[("Head", 1.0)]).unwrap();
+        look_at.max_yaw = 90.0_f32.to_radians();
+
+        let mut pose = skeleton.base_pose();
+        look_at.apply(&skeleton, &mut pose, Vec3::X, Mat4::IDENTITY, 0.0);
+        assert!(facing(&skeleton, &pose).abs_diff_eq(Vec3::X, 1e-5));
+
+        look_at.max_yaw = 45.0_f32.to_radians();
+        let mut pose = skeleton.base_pose();
+        look_at.apply(&skeleton, &mut pose, Vec3::X, Mat4::IDENTITY, 0.0);
+        let expected = Vec3::new(1.0, 0.0, 1.0).normalize();
+        assert!(facing(&skeleton, &pose).abs_diff_eq(expected, 1e-5));
+    }
+
+    #[test]
+    fn target_is_in_world_space() {
+        let skeleton = head_skeleton();
+        let mut look_at = LookAt::new(&skeleton, vec![(0, 1.0)]).unwrap();
+        look_at.max_pitch = 90.0_f32.to_radians();
+
+        // Model is 2 units to the right, so a target 2 units right and 1 up is straight above
+        let model_matrix = Mat4::from_translation(Vec3::new(2.0, 0.0, 0.0));
+        let mut pose = skeleton.base_pose();
+        look_at.apply(
+            &skeleton,
+            &mut pose,
+            Vec3::new(2.0, 1.0, 0.0),
+            model_matrix,
+            0.0,
+        );
+        assert!(facing(&skeleton, &pose).abs_diff_eq(Vec3::Y, 1e-4));
+    }
+}
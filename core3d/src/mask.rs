@@ -0,0 +1,91 @@
+use crate::Skeleton;
+
+/// A weight for each joint in a skeleton, used to limit an animation layer to part of the body.
+/// A weight of 1 means the joint is fully affected and 0 means it is unaffected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JointMask {
+    pub weights: Vec<f32>,
+}
+
+impl JointMask {
+    /// A mask that affects every joint in the skeleton
+    pub fn all(skeleton: &Skeleton) -> Self {
+        JointMask {
+            weights: vec![1.0; skeleton.joints.len()],
+        }
+    }
+
+    /// A mask that doesn't affect any joints in the skeleton
+    pub fn none(skeleton: &Skeleton) -> Self {
+        JointMask {
+            weights: vec![0.0; skeleton.joints.len()],
+        }
+    }
+
+    /// A mask that affects each of the named joints and all of their descendants,
+    /// e.g. `["Spine2"]` for an upper body layer.
+    /// Returns None if any of the names aren't in the skeleton.
+    pub fn from_joint_names(skeleton: &Skeleton, names: &[&str]) -> Option<Self> {
+        let mut mask = JointMask::none(skeleton);
+        for name in names {
+            let index = skeleton.joint_index(name)?;
+            mask.set_with_descendants(skeleton, index, 1.0);
+        }
+        Some(mask)
+    }
+
+    /// Sets the weight of the joint at `index` and all of its descendants
+    pub fn set_with_descendants(&mut self, skeleton: &Skeleton, index: usize, weight: f32) {
+        // Guard against malformed skeletons with cycles
+        let mut visited = vec![false; self.weights.len()];
+        let mut stack = vec![index];
+        while let Some(i) = stack.pop() {
+            if std::mem::replace(&mut visited[i], true) {
+                continue;
+            }
+            self.weights[i] = weight;
+            stack.extend(skeleton.joints[i].children.iter().map(|c| *c as usize));
+        }
+    }
+
+    /// The weight of the joint at `index`, or 0 if it is out of range
+    pub fn weight(&self, index: usize) -> f32 {
+        self.weights.get(index).copied().unwrap_or(0.0)
+    }
+
+    /// Flips the mask so affected joints become unaffected and vice versa
+    pub fn inverted(&self) -> Self {
+        JointMask {
+            weights: self.weights.iter().map(|w| 1.0 - w).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Joint;
+
+    #[test]
+    fn mask_includes_descendants() {
+        // 0 -> 1 -> 2, 0 -> 3
+        let joint = |name: &str, children: Vec<u8>, parent| Joint {
+            name: name.to_string(),
+            children,
+            parent,
+            ..Default::default()
+        };
+        let skeleton = Skeleton {
+            joints: vec![
+                joint("Hips", vec![1, 3], None),
+                joint("Spine", vec![2], Some(0)),
+                joint("Head", vec![], Some(1)),
+                joint("Leg", vec![], Some(0)),
+            ],
+        };
+
+        let mask = JointMask::from_joint_names(&skeleton, &["Spine"]).unwrap();
+        assert_eq!(mask.weights, vec![0.0, 1.0, 1.0, 0.0]);
+        assert!(JointMask::from_joint_names(&skeleton, &["Tail"]).is_none());
+    }
+}
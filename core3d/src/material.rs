@@ -0,0 +1,102 @@
+use glam::{Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+
+/// How a material's alpha is used
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AlphaMode {
+    /// Alpha is ignored
+    #[default]
+    Opaque,
+    /// Pixels with alpha below [`Material::alpha_cutoff`] aren't drawn
+    Mask,
+    /// Blended with what's behind it
+    Blend,
+}
+
+/// How the surface of a mesh is drawn
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Material {
+    pub name: String,
+    /// Index into [`crate::Model::textures`]
+    pub texture: Option<u8>,
+    /// Multiplied with the texture colour, or used on its own if there's no texture
+    pub base_color: Vec4,
+    /// Added to the colour
+    pub emissive: Vec3,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    /// Whether back faces are drawn too
+    pub double_sided: bool,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            texture: None,
+            base_color: Vec4::ONE,
+            emissive: Vec3::ZERO,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+impl Material {
+    /// A plain white material for each texture, for models from before materials existed where
+    /// meshes referred to textures directly
+    pub fn from_textures(count: usize) -> Vec<Material> {
+        if count == 0 {
+            return vec![Material::default()];
+        }
+        (0..count)
+            .map(|i| Material {
+                texture: Some(i as u8),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// The alpha value pixels have to reach to be drawn, if the material uses alpha testing
+    pub fn alpha_test(&self) -> Option<u8> {
+        (self.alpha_mode == AlphaMode::Mask)
+            .then(|| (self.alpha_cutoff.clamp(0.0, 1.0) * 255.0).round() as u8)
+    }
+
+    /// Base colour as 8 bit RGBA, with red in the lowest byte
+    pub fn base_color_rgba8(&self) -> u32 {
+        rgba8(self.base_color)
+    }
+
+    /// Emissive colour as 8 bit RGBA, with red in the lowest byte
+    pub fn emissive_rgba8(&self) -> u32 {
+        rgba8(self.emissive.extend(0.0))
+    }
+}
+
+fn rgba8(color: Vec4) -> u32 {
+    let [r, g, b, a] = (color.clamp(Vec4::ZERO, Vec4::ONE) * 255.0)
+        .round()
+        .to_array()
+        .map(|c| c as u8);
+    u32::from_le_bytes([r, g, b, a])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_to_gpu_values() {
+        let material = Material {
+            base_color: Vec4::new(1.0, 0.5, 0.0, 1.0),
+            alpha_mode: AlphaMode::Mask,
+            alpha_cutoff: 0.3,
+            ..Default::default()
+        };
+        assert_eq!(material.base_color_rgba8(), 0xFF_00_80_FF);
+        assert_eq!(material.alpha_test(), Some(77));
+        assert_eq!(Material::default().alpha_test(), None);
+    }
+}
@@ -0,0 +1,437 @@
+use crate::{
+    mask::JointMask, player::AnimationPlayer, scene::Scene, Animation, JointTransform, Marker,
+    Skeleton,
+};
+
+/// An animation playing in an [`AnimationMixer`]
+#[derive(Clone, Debug)]
+pub struct MixerClip {
+    pub player: AnimationPlayer,
+    pub weight: f32,
+    target_weight: f32,
+    /// How much the weight changes per second while fading towards `target_weight`
+    fade_rate: f32,
+}
+
+impl MixerClip {
+    fn new(player: AnimationPlayer, weight: f32) -> Self {
+        MixerClip {
+            player,
+            weight,
+            target_weight: weight,
+            fade_rate: 0.0,
+        }
+    }
+
+    /// Index into the model's animations
+    pub fn animation(&self) -> usize {
+        self.player.animation
+    }
+
+    fn fade_to(&mut self, weight: f32, duration: f32) {
+        self.target_weight = weight;
+        if duration > 0.0 {
+            self.fade_rate = (weight - self.weight).abs() / duration;
+        } else {
+            self.weight = weight;
+        }
+    }
+
+    pub fn is_fading(&self) -> bool {
+        self.weight != self.target_weight
+    }
+}
+
+/// A marker that was passed by a clip playing in an [`AnimationMixer`]
+#[derive(Clone, Debug)]
+pub struct MarkerEvent<'a> {
+    /// Index into the model's animations
+    pub animation: usize,
+    pub marker: &'a Marker,
+    /// The weight of the clip that passed the marker, so events from clips that are fading out
+    /// can be ignored
+    pub weight: f32,
+}
+
+/// How a [`MixerLayer`] combines its clips with the layers beneath it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LayerMode {
+    /// Blends from the pose beneath towards the layer's pose by the layer's weight
+    #[default]
+    Override,
+    /// Adds the layer's clips on top of the pose beneath, the clips should be additive
+    /// animations created with [`Animation::to_additive`]
+    Additive,
+}
+
+/// A group of clips blended together, and then combined with the layers beneath it
+#[derive(Clone, Debug, Default)]
+pub struct MixerLayer {
+    pub mode: LayerMode,
+    /// Limits which joints this layer affects, or all joints if None
+    pub mask: Option<JointMask>,
+    pub weight: f32,
+    clips: Vec<MixerClip>,
+}
+
+impl MixerLayer {
+    pub fn new(mode: LayerMode, mask: Option<JointMask>) -> Self {
+        MixerLayer {
+            mode,
+            mask,
+            weight: 1.0,
+            clips: Vec::new(),
+        }
+    }
+
+    pub fn clips(&self) -> &[MixerClip] {
+        &self.clips
+    }
+
+    pub fn clip_mut(&mut self, animation: usize) -> Option<&mut MixerClip> {
+        self.clips.iter_mut().find(|c| c.animation() == animation)
+    }
+
+    /// Immediately plays an animation at full weight, stopping any other clips
+    pub fn play(&mut self, player: AnimationPlayer) {
+        self.clips.clear();
+        self.clips.push(MixerClip::new(player, 1.0));
+    }
+
+    /// Fades an animation in to full weight over `duration` seconds while fading every other
+    /// clip out. If the animation is already playing it keeps its existing player and continues
+    /// from its current time.
+    pub fn crossfade(&mut self, player: AnimationPlayer, duration: f32) {
+        for clip in &mut self.clips {
+            clip.fade_to(0.0, duration);
+        }
+        self.fade_to(player, 1.0, duration);
+    }
+
+    /// Fades an animation to `weight` over `duration` seconds without affecting other clips,
+    /// starting it at zero weight if it isn't already playing
+    pub fn fade_to(&mut self, player: AnimationPlayer, weight: f32, duration: f32) {
+        let animation = player.animation;
+        let clip = match self.clips.iter().position(|c| c.animation() == animation) {
+            Some(i) => &mut self.clips[i],
+            None => {
+                self.clips.push(MixerClip::new(player, 0.0));
+                self.clips.last_mut().expect("Clip was just pushed")
+            }
+        };
+        clip.fade_to(weight, duration);
+    }
+
+    /// Stops all clips
+    pub fn stop(&mut self) {
+        self.clips.clear();
+    }
+
+    fn update(&mut self, dt: f32) {
+        for clip in &mut self.clips {
+            clip.player.update(dt);
+
+            if clip.is_fading() {
+                let step = clip.fade_rate * dt;
+                let diff = clip.target_weight - clip.weight;
+                clip.weight = if diff.abs() <= step {
+                    clip.target_weight
+                } else {
+                    clip.weight + step.copysign(diff)
+                };
+            }
+        }
+
+        self.clips
+            .retain(|c| c.weight > 0.0 || c.target_weight > 0.0);
+    }
+
+    fn joint_weight(&self, index: usize) -> f32 {
+        self.weight * self.mask.as_ref().map_or(1.0, |m| m.weight(index))
+    }
+
+    /// Combines this layer's clips with `pose`
+    fn apply(&self, pose: &mut [JointTransform], animations: &[Animation], skeleton: &Skeleton) {
+        if self.weight <= 0.0 || self.clips.is_empty() {
+            return;
+        }
+
+        match self.mode {
+            LayerMode::Override => {
+                // If the weights add up to less than 1, the remainder is made up by the pose
+                // beneath this layer
+                let total_weight: f32 = self.clips.iter().map(|c| c.weight).sum();
+                let mut layer_pose = pose.to_vec();
+                let mut accumulated = (1.0 - total_weight).max(0.0);
+
+                for clip in &self.clips {
+                    if clip.weight <= 0.0 {
+                        continue;
+                    }
+
+                    let clip_pose = clip.player.sample(&animations[clip.animation()], skeleton);
+
+                    // Blending each clip in by its share of the weight so far gives a
+                    // normalised weighted average across all clips
+                    accumulated += clip.weight;
+                    blend_poses(&mut layer_pose, &clip_pose, clip.weight / accumulated);
+                }
+
+                for (i, (joint, layer_joint)) in pose.iter_mut().zip(&layer_pose).enumerate() {
+                    *joint = joint.blend(layer_joint, self.joint_weight(i));
+                }
+            }
+            LayerMode::Additive => {
+                for clip in &self.clips {
+                    let delta = clip.player.sample(&animations[clip.animation()], skeleton);
+                    for (i, (joint, delta)) in pose.iter_mut().zip(&delta).enumerate() {
+                        *joint = joint.add(delta, clip.weight * self.joint_weight(i));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Plays several animations at once with individual weights and blends them into a single pose,
+/// so the model can smoothly crossfade from one animation to another.
+///
+/// Clips are organised into layers, which are applied in order on top of the skeleton's base
+/// pose. The first layer always exists and covers the whole skeleton, and the methods on the
+/// mixer itself such as [`AnimationMixer::crossfade`] act on it. Further layers can be added for
+/// things like an upper body override or additive aim offsets.
+#[derive(Clone, Debug)]
+pub struct AnimationMixer {
+    layers: Vec<MixerLayer>,
+}
+
+impl Default for AnimationMixer {
+    fn default() -> Self {
+        AnimationMixer {
+            layers: vec![MixerLayer::new(LayerMode::Override, None)],
+        }
+    }
+}
+
+impl AnimationMixer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a layer on top of all existing layers, returning its index
+    pub fn add_layer(&mut self, layer: MixerLayer) -> usize {
+        self.layers.push(layer);
+        self.layers.len() - 1
+    }
+
+    pub fn layer(&self, index: usize) -> Option<&MixerLayer> {
+        self.layers.get(index)
+    }
+
+    pub fn layer_mut(&mut self, index: usize) -> Option<&mut MixerLayer> {
+        self.layers.get_mut(index)
+    }
+
+    pub fn base_layer(&mut self) -> &mut MixerLayer {
+        &mut self.layers[0]
+    }
+
+    /// The clips playing on the base layer
+    pub fn clips(&self) -> &[MixerClip] {
+        self.layers[0].clips()
+    }
+
+    /// See [`MixerLayer::play`]
+    pub fn play(&mut self, player: AnimationPlayer) {
+        self.base_layer().play(player);
+    }
+
+    /// See [`MixerLayer::crossfade`]
+    pub fn crossfade(&mut self, player: AnimationPlayer, duration: f32) {
+        self.base_layer().crossfade(player, duration);
+    }
+
+    /// See [`MixerLayer::fade_to`]
+    pub fn fade_to(&mut self, player: AnimationPlayer, weight: f32, duration: f32) {
+        self.base_layer().fade_to(player, weight, duration);
+    }
+
+    /// Stops all clips on every layer
+    pub fn stop(&mut self) {
+        for layer in &mut self.layers {
+            layer.stop();
+        }
+    }
+
+    /// Advances all clips by `dt` seconds and progresses any fades.
+    /// Clips that have finished fading out are removed.
+    pub fn update(&mut self, dt: f32) {
+        for layer in &mut self.layers {
+            layer.update(dt);
+        }
+    }
+
+    /// Every marker passed by any clip on any layer during the last update
+    pub fn crossed_markers<'a>(&self, animations: &'a [Animation]) -> Vec<MarkerEvent<'a>> {
+        self.layers
+            .iter()
+            .flat_map(|layer| &layer.clips)
+            .flat_map(|clip| {
+                clip.player
+                    .crossed_markers(&animations[clip.animation()])
+                    .into_iter()
+                    .map(|marker| MarkerEvent {
+                        animation: clip.animation(),
+                        marker,
+                        weight: clip.weight,
+                    })
+            })
+            .collect()
+    }
+
+    /// The root motion of the clips on the base layer over the last update, blended by weight.
+    /// See [`AnimationPlayer::root_motion`].
+    pub fn root_motion(&self, animations: &[Animation]) -> JointTransform {
+        let mut motion = JointTransform::default();
+        let mut accumulated = 0.0;
+
+        for clip in self.clips() {
+            if clip.weight <= 0.0 {
+                continue;
+            }
+
+            accumulated += clip.weight;
+            let clip_motion = clip.player.root_motion(&animations[clip.animation()]);
+            motion = motion.blend(&clip_motion, clip.weight / accumulated);
+        }
+
+        motion
+    }
+
+    /// Samples the morph target weights of the clips on the base layer into `weights`, blended by
+    /// weight. `weights` has the weights for every mesh in the model, and should start as each
+    /// mesh's default weights, which are kept for targets the clips don't animate.
+    pub fn morph_weights(&self, animations: &[Animation], scene: &Scene, weights: &mut [Vec<f32>]) {
+        let defaults = weights.to_vec();
+        let mut accumulated = 0.0;
+
+        for clip in self.clips() {
+            let animation = &animations[clip.animation()];
+            if clip.weight <= 0.0 || animation.morph_weights.is_empty() {
+                continue;
+            }
+
+            let mut clip_weights = defaults.clone();
+            for morph in &animation.morph_weights {
+                morph.sample(clip.player.time(), scene, &mut clip_weights);
+            }
+
+            accumulated += clip.weight;
+            let s = clip.weight / accumulated;
+            for (mesh, clip_mesh) in weights.iter_mut().zip(&clip_weights) {
+                for (w, clip_w) in mesh.iter_mut().zip(clip_mesh) {
+                    *w += (clip_w - *w) * s;
+                }
+            }
+        }
+    }
+
+    /// Samples every playing clip and combines them into one pose
+    pub fn sample(&self, animations: &[Animation], skeleton: &Skeleton) -> Vec<JointTransform> {
+        let mut pose = skeleton.base_pose();
+        for layer in &self.layers {
+            layer.apply(&mut pose, animations, skeleton);
+        }
+        pose
+    }
+}
+
+/// Blends each joint of `pose` towards the matching joint of `other` by `s`
+pub fn blend_poses(pose: &mut [JointTransform], other: &[JointTransform], s: f32) {
+    for (joint, other) in pose.iter_mut().zip(other) {
+        *joint = joint.blend(other, s);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::{player::PlaybackMode, Interpolation, Joint, JointAnimation, Track};
+
+    fn constant_animation(pos: Vec3) -> Animation {
+        Animation {
+            joints: vec![JointAnimation {
+                translations: Track::new(Interpolation::Linear, vec![(0.0, pos), (1.0, pos)]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn crossfade_blends_halfway() {
+        let skeleton = Skeleton {
+            joints: vec![Joint::default()],
+        };
+        let animations = [constant_animation(Vec3::ZERO), constant_animation(Vec3::X)];
+
+        let mut mixer = AnimationMixer::new();
+        mixer.play(AnimationPlayer::new(0, &animations[0], PlaybackMode::Loop));
+        mixer.crossfade(
+            AnimationPlayer::new(1, &animations[1], PlaybackMode::Loop),
+            1.0,
+        );
+        mixer.update(0.5);
+
+        let pose = mixer.sample(&animations, &skeleton);
+        assert!(pose[0].pos.abs_diff_eq(Vec3::new(0.5, 0.0, 0.0), 1e-5));
+
+        mixer.update(0.5);
+        assert_eq!(mixer.clips().len(), 1);
+        let pose = mixer.sample(&animations, &skeleton);
+        assert!(pose[0].pos.abs_diff_eq(Vec3::X, 1e-5));
+    }
+
+    #[test]
+    fn additive_layer_respects_mask() {
+        let skeleton = Skeleton {
+            joints: vec![
+                Joint {
+                    children: vec![1],
+                    ..Default::default()
+                },
+                Joint {
+                    name: "Arm".to_string(),
+                    parent: Some(0),
+                    ..Default::default()
+                },
+            ],
+        };
+        let wave = Animation {
+            joints: vec![
+                JointAnimation {
+                    translations: Track::new(Interpolation::Linear, vec![(0.0, Vec3::Y)]),
+                    ..Default::default()
+                };
+                2
+            ],
+            ..Default::default()
+        };
+        let animations = [wave.to_additive(&skeleton.base_pose())];
+
+        let mut mixer = AnimationMixer::new();
+        let mask = JointMask::from_joint_names(&skeleton, &["Arm"]);
+        let layer = mixer.add_layer(MixerLayer::new(LayerMode::Additive, mask));
+        mixer.layer_mut(layer).unwrap().play(AnimationPlayer::new(
+            0,
+            &animations[0],
+            PlaybackMode::Loop,
+        ));
+
+        let pose = mixer.sample(&animations, &skeleton);
+        assert_eq!(pose[0].pos, Vec3::ZERO);
+        assert!(pose[1].pos.abs_diff_eq(Vec3::Y, 1e-6));
+    }
+}
@@ -0,0 +1,124 @@
+//! Morph targets (blend shapes), which move a mesh's vertices by a weighted sum of offsets.
+//!
+//! The vertex shader has no room left for per-target offsets, so targets are blended on the CPU
+//! into a copy of the vertices, and only the [`MAX_ACTIVE_TARGETS`] with the largest weights are
+//! used each frame.
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::{scene::Scene, Track, Vertex};
+
+/// How many targets are blended into a mesh at once
+pub const MAX_ACTIVE_TARGETS: usize = 4;
+
+/// Offsets for each vertex of a mesh
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct MorphTarget {
+    pub name: String,
+    pub positions: Vec<Vec3>,
+    /// Empty if the target doesn't change the normals
+    pub normals: Vec<Vec3>,
+}
+
+/// Animated weights for the morph targets of a scene node's meshes
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct MorphAnimation {
+    /// Index into [`Scene::nodes`]
+    pub node: usize,
+    /// One track for each target
+    pub weights: Vec<Track<f32>>,
+}
+
+impl MorphAnimation {
+    /// Samples the weights at time `t` into `weights`, the weights of every mesh in the model.
+    /// Targets without keyframes keep the weight they already have.
+    pub fn sample(&self, t: f32, scene: &Scene, weights: &mut [Vec<f32>]) {
+        let Some(node) = scene.nodes.get(self.node) else {
+            return;
+        };
+        for mesh in &node.meshes {
+            let Some(mesh_weights) = weights.get_mut(*mesh) else {
+                continue;
+            };
+            for (weight, track) in mesh_weights.iter_mut().zip(&self.weights) {
+                if let Some(w) = track.sample(t) {
+                    *weight = w;
+                }
+            }
+        }
+    }
+}
+
+/// The targets with the largest weights, up to [`MAX_ACTIVE_TARGETS`] of them, skipping any with
+/// a weight of 0
+pub fn active_targets(weights: &[f32]) -> Vec<(usize, f32)> {
+    let mut active: Vec<(usize, f32)> = weights
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, w)| *w != 0.0)
+        .collect();
+    active.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+    active.truncate(MAX_ACTIVE_TARGETS);
+    active
+}
+
+/// Writes `verts` moved by the active `targets` into `out`, which must be the same length
+pub fn blend(verts: &[Vertex], targets: &[MorphTarget], weights: &[f32], out: &mut [Vertex]) {
+    out.copy_from_slice(verts);
+    let active = active_targets(weights);
+    for (target, weight) in active
+        .iter()
+        .filter_map(|(i, w)| Some((targets.get(*i)?, *w)))
+    {
+        for (v, offset) in out.iter_mut().zip(&target.positions) {
+            v.pos += *offset * weight;
+        }
+        for (v, offset) in out.iter_mut().zip(&target.normals) {
+            v.norm += *offset * weight;
+        }
+    }
+    if active
+        .iter()
+        .any(|(i, _)| targets.get(*i).is_some_and(|t| !t.normals.is_empty()))
+    {
+        for v in out.iter_mut() {
+            v.norm = v.norm.normalize_or_zero();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blends_largest_targets() {
+        let verts = [Vertex {
+            norm: Vec3::Y,
+            ..Default::default()
+        }];
+        let targets: Vec<MorphTarget> = (0..6)
+            .map(|i| MorphTarget {
+                name: format!("Target {i}"),
+                positions: vec![Vec3::X * (i + 1) as f32],
+                normals: Vec::new(),
+            })
+            .collect();
+        // Target 0 has the smallest weight so is left out
+        let weights = [0.1, 0.5, -0.5, 0.0, 0.5, 0.5];
+        assert_eq!(
+            active_targets(&weights)
+                .iter()
+                .map(|(i, _)| *i)
+                .collect::<Vec<_>>(),
+            [1, 2, 4, 5]
+        );
+
+        let mut out = verts;
+        blend(&verts, &targets, &weights, &mut out);
+        assert_eq!(out[0].pos, Vec3::X * (2.0 - 3.0 + 5.0 + 6.0) * 0.5);
+        assert_eq!(out[0].norm, Vec3::Y);
+    }
+}
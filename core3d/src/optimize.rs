@@ -0,0 +1,310 @@
+//! Reordering a mesh's triangles and vertices so the GPU does less work drawing it, and turning
+//! triangle lists into strips.
+//!
+//! How well a mesh uses the post-transform vertex cache is measured by its ACMR (average cache
+//! miss ratio), the number of vertices that have to be transformed per triangle. It's 3 with no
+//! reuse at all, and can get down to about 0.5 for a large regular grid.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Mesh;
+
+/// Entries in the simulated post-transform vertex cache. The size of the PICA200's cache isn't
+/// documented, so this is a middle ground that works well for smaller and larger caches.
+pub const VERTEX_CACHE_SIZE: usize = 16;
+
+/// How a mesh's indices make triangles
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topology {
+    /// Every 3 indices are a triangle
+    #[default]
+    Triangles,
+    /// Every index after the first 2 makes a triangle with the 2 before it, flipping every other
+    /// triangle to keep the winding the same. Triangles with repeated indices are used to join
+    /// strips together and aren't drawn.
+    TriangleStrip,
+}
+
+/// The triangles drawn by `inds`, in the order they're drawn and with the winding they're drawn
+/// with, leaving out the degenerate ones in strips
+pub fn triangle_list(inds: &[u16], topology: Topology) -> Vec<u16> {
+    match topology {
+        Topology::Triangles => inds.to_vec(),
+        Topology::TriangleStrip => inds
+            .windows(3)
+            .enumerate()
+            .filter(|(_, t)| t[0] != t[1] && t[1] != t[2] && t[0] != t[2])
+            .flat_map(|(i, t)| {
+                if i.is_multiple_of(2) {
+                    [t[0], t[1], t[2]]
+                } else {
+                    [t[1], t[0], t[2]]
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Average number of vertices transformed per triangle drawn, with a FIFO cache of `cache_size`
+/// vertices like most GPUs have
+pub fn acmr(inds: &[u16], topology: Topology, cache_size: usize) -> f32 {
+    let triangles = triangle_list(inds, topology).len() / 3;
+    if triangles == 0 {
+        return 0.0;
+    }
+    let mut cache = std::collections::VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for i in inds {
+        if !cache.contains(i) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(*i);
+        }
+    }
+    misses as f32 / triangles as f32
+}
+
+/// How much a vertex is worth drawing soon, from Tom Forsyth's "Linear-Speed Vertex Cache
+/// Optimisation". Vertices near the front of the cache and with few triangles left score higher.
+fn vertex_score(cache_position: Option<usize>, remaining: usize, cache_size: usize) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        // Used by the last triangle, so a little worse to stop strips doubling back on themselves
+        Some(p) if p < 3 => 0.75,
+        Some(p) => (1.0 - (p - 3) as f32 / (cache_size - 3) as f32).powf(1.5),
+    };
+    cache_score + 2.0 * (remaining as f32).powf(-0.5)
+}
+
+/// Reorders the triangles of `inds`, a triangle list of `vertex_count` vertices, so vertices
+/// stay in a cache of `cache_size` between uses
+pub fn optimize_vertex_cache(inds: &mut [u16], vertex_count: usize, cache_size: usize) {
+    assert!(
+        cache_size > 3,
+        "The cache needs to hold more than a triangle"
+    );
+    let triangle_count = inds.len() / 3;
+    let triangles: Vec<[usize; 3]> = inds
+        .chunks_exact(3)
+        .map(|t| [t[0], t[1], t[2]].map(usize::from))
+        .collect();
+
+    // Triangles not yet drawn that use each vertex
+    let mut vertex_triangles = vec![Vec::new(); vertex_count];
+    for (i, t) in triangles.iter().enumerate() {
+        for v in t {
+            vertex_triangles[*v].push(i);
+        }
+    }
+    let mut scores: Vec<f32> = vertex_triangles
+        .iter()
+        .map(|t| vertex_score(None, t.len(), cache_size))
+        .collect();
+    let triangle_score =
+        |t: &[usize; 3], scores: &[f32]| -> f32 { t.iter().map(|v| scores[*v]).sum() };
+
+    let mut drawn = vec![false; triangle_count];
+    let mut order = Vec::with_capacity(triangle_count);
+    let mut cache: Vec<usize> = Vec::with_capacity(cache_size + 3);
+    // Where to look for a triangle when none of the cached vertices have any left
+    let mut next_undrawn = 0;
+
+    while order.len() < triangle_count {
+        let best = cache
+            .iter()
+            .flat_map(|v| vertex_triangles[*v].iter().copied())
+            .map(|t| (t, triangle_score(&triangles[t], &scores)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(t, _)| t);
+        let best = best.unwrap_or_else(|| {
+            while drawn[next_undrawn] {
+                next_undrawn += 1;
+            }
+            next_undrawn
+        });
+
+        drawn[best] = true;
+        order.push(best);
+        for v in triangles[best] {
+            vertex_triangles[v].retain(|t| *t != best);
+            cache.retain(|c| *c != v);
+        }
+        cache.splice(0..0, triangles[best]);
+        for evicted in cache.drain(cache_size.min(cache.len())..) {
+            scores[evicted] = vertex_score(None, vertex_triangles[evicted].len(), cache_size);
+        }
+        for (p, v) in cache.iter().enumerate() {
+            scores[*v] = vertex_score(Some(p), vertex_triangles[*v].len(), cache_size);
+        }
+    }
+
+    for (new, old) in order.iter().enumerate() {
+        for (i, v) in triangles[*old].iter().enumerate() {
+            inds[new * 3 + i] = *v as u16;
+        }
+    }
+}
+
+/// Moves `mesh`'s vertices into the order its indices first use them, so they're read from
+/// memory in order. Vertices that aren't used go at the end.
+pub fn optimize_vertex_fetch(mesh: &mut Mesh) {
+    let mut new_index: Vec<Option<u16>> = vec![None; mesh.verts.len()];
+    let mut order = Vec::with_capacity(mesh.verts.len());
+    for i in &mut mesh.inds {
+        *i = *new_index[*i as usize].get_or_insert_with(|| {
+            order.push(*i as usize);
+            (order.len() - 1) as u16
+        });
+    }
+    order.extend((0..mesh.verts.len()).filter(|i| new_index[*i].is_none()));
+
+    mesh.verts = order.iter().map(|i| mesh.verts[*i]).collect();
+    for target in &mut mesh.morph_targets {
+        for values in [&mut target.positions, &mut target.normals] {
+            if !values.is_empty() {
+                *values = order.iter().map(|i| values[*i]).collect();
+            }
+        }
+    }
+}
+
+/// Joins the triangles of a triangle list into strips, stitched together with degenerate
+/// triangles into a single strip with the same winding
+pub fn to_strip(inds: &[u16]) -> Vec<u16> {
+    let triangles: Vec<[u16; 3]> = inds.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+    // Triangles with each edge going around them in their winding order
+    let mut edges: std::collections::HashMap<(u16, u16), Vec<usize>> = Default::default();
+    for (i, t) in triangles.iter().enumerate() {
+        for e in 0..3 {
+            edges.entry((t[e], t[(e + 1) % 3])).or_default().push(i);
+        }
+    }
+
+    let mut used = vec![false; triangles.len()];
+    // The strip made by starting at `start` with its vertices in that order, without marking
+    // anything as used
+    let grow = |start: [u16; 3], used: &[bool]| -> (Vec<u16>, Vec<usize>) {
+        let mut strip = start.to_vec();
+        let mut added = Vec::new();
+        let taken = |t: usize, added: &[usize]| used[t] || added.contains(&t);
+        loop {
+            let [x, y] = [strip[strip.len() - 2], strip[strip.len() - 1]];
+            // The next triangle is (x, y, z), which is flipped if it's an odd one
+            let edge = if strip.len().is_multiple_of(2) {
+                (x, y)
+            } else {
+                (y, x)
+            };
+            let next = edges
+                .get(&edge)
+                .and_then(|ts| ts.iter().find(|t| !taken(**t, &added)).copied());
+            let Some(next) = next else {
+                break;
+            };
+            let z = *triangles[next]
+                .iter()
+                .find(|v| **v != x && **v != y)
+                .expect("Triangle has 3 different vertices");
+            strip.push(z);
+            added.push(next);
+        }
+        (strip, added)
+    };
+
+    let mut out: Vec<u16> = Vec::with_capacity(inds.len());
+    for (i, t) in triangles.iter().enumerate() {
+        if used[i] {
+            continue;
+        }
+        used[i] = true;
+        if t[0] == t[1] || t[1] == t[2] || t[0] == t[2] {
+            continue;
+        }
+        let (strip, added) = [[t[0], t[1], t[2]], [t[1], t[2], t[0]], [t[2], t[0], t[1]]]
+            .into_iter()
+            .map(|start| grow(start, &used))
+            .max_by_key(|(strip, _)| strip.len())
+            .expect("There are 3 rotations");
+        for t in added {
+            used[t] = true;
+        }
+
+        if let Some(last) = out.last().copied() {
+            out.push(last);
+            out.push(strip[0]);
+            // The strip has to start on an even triangle to keep its winding
+            if !out.len().is_multiple_of(2) {
+                out.push(strip[0]);
+            }
+        }
+        out.extend(strip);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Triangles of a `size` by `size` grid of quads, going along each row
+    fn grid(size: u16) -> Vec<u16> {
+        let row = size + 1;
+        (0..size)
+            .flat_map(|y| {
+                (0..size).flat_map(move |x| {
+                    let i = y * row + x;
+                    [i, i + 1, i + row, i + 1, i + row + 1, i + row]
+                })
+            })
+            .collect()
+    }
+
+    /// Each triangle rotated to start with its smallest index, sorted, so lists with the same
+    /// triangles and winding can be compared
+    fn normalized(inds: &[u16]) -> Vec<[u16; 3]> {
+        let mut triangles: Vec<[u16; 3]> = inds
+            .chunks_exact(3)
+            .map(|t| {
+                let mut t = [t[0], t[1], t[2]];
+                let smallest = (0..3).min_by_key(|i| t[*i]).unwrap();
+                t.rotate_left(smallest);
+                t
+            })
+            .collect();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn cache_optimisation_lowers_acmr() {
+        let inds = grid(20);
+        let before = acmr(&inds, Topology::Triangles, VERTEX_CACHE_SIZE);
+        let mut optimized = inds.clone();
+        optimize_vertex_cache(&mut optimized, 21 * 21, VERTEX_CACHE_SIZE);
+        let after = acmr(&optimized, Topology::Triangles, VERTEX_CACHE_SIZE);
+
+        assert_eq!(normalized(&optimized), normalized(&inds));
+        assert!(after < before * 0.9, "ACMR went from {before} to {after}");
+        assert!(after < 0.8, "ACMR is {after}");
+    }
+
+    #[test]
+    fn strips_draw_same_triangles() {
+        let mut inds = grid(6);
+        // A triangle on its own, to need stitching
+        inds.extend([100, 101, 102]);
+        let strip = to_strip(&inds);
+
+        assert!(strip.len() < inds.len());
+        assert_eq!(
+            normalized(&triangle_list(&strip, Topology::TriangleStrip)),
+            normalized(&inds)
+        );
+    }
+}
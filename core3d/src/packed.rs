@@ -0,0 +1,314 @@
+//! Smaller vertex layouts made of the integer attribute types the PICA200 can read natively.
+//!
+//! The GPU reads integer attributes as their raw value, so they have to be scaled back in the
+//! vertex shader. Positions are scaled by folding [`Dequantize::pos_matrix`] into the joint
+//! matrices, and the rest by the uniforms from [`Dequantize::uv_transform`] and
+//! [`VertexFormat::weight_scale`].
+
+use glam::{IVec2, IVec3, Mat4, Vec2, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    layout::{Attribute, VertexLayout},
+    Vertex,
+};
+
+/// Packed normals are multiplied by this to get back a unit vector
+pub const NORMAL_SCALE: f32 = 1.0 / 127.0;
+/// Packed weights are multiplied by this to get back a weight between 0 and 1
+pub const WEIGHT_SCALE: f32 = 1.0 / 255.0;
+
+/// How a mesh's vertices are laid out for the GPU
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VertexFormat {
+    /// [`Vertex`], with everything as floats
+    #[default]
+    Full,
+    /// [`PackedVertex`], with everything as bytes or shorts
+    Packed,
+}
+
+impl VertexFormat {
+    /// Size in bytes of each vertex
+    pub fn stride(self) -> usize {
+        match self {
+            VertexFormat::Full => Vertex::STRIDE,
+            VertexFormat::Packed => PackedVertex::STRIDE,
+        }
+    }
+
+    /// What the GPU needs to know to read the vertices, with the shader expecting position,
+    /// normal, texture coordinates, joints and weights in registers `v0` to `v4`
+    pub fn attributes(self) -> &'static [Attribute] {
+        match self {
+            VertexFormat::Full => Vertex::ATTRIBUTES,
+            VertexFormat::Packed => PackedVertex::ATTRIBUTES,
+        }
+    }
+
+    /// What the shader has to multiply the weights attribute by
+    pub fn weight_scale(self) -> f32 {
+        match self {
+            VertexFormat::Full => 1.0,
+            VertexFormat::Packed => WEIGHT_SCALE,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PackedVertex {
+    /// Position relative to the middle of the mesh, see [`Dequantize::pos_matrix`]
+    pub pos: [i16; 3],
+    /// See [`Dequantize::uv_transform`]
+    pub tc: [i16; 2],
+    /// Multiplied by [`NORMAL_SCALE`]
+    pub norm: [i8; 3],
+    /// Indices for which joints influence this vertex
+    pub joints: [u8; 3],
+    /// Multiplied by [`WEIGHT_SCALE`], always adding up to 255
+    pub weights: [u8; 3],
+}
+
+// SAFETY: Every field is made of integers, which are valid for any bit pattern. It isn't `Pod`
+// because of the padding byte at the end.
+unsafe impl bytemuck::Zeroable for PackedVertex {}
+unsafe impl bytemuck::AnyBitPattern for PackedVertex {}
+
+crate::vertex_layout!(PackedVertex {
+    pos => 0,
+    tc => 2,
+    norm => 1,
+    joints => 3,
+    weights => 4,
+});
+
+/// How to get the original positions and texture coordinates of a mesh back from its
+/// [`PackedVertex`]es
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Dequantize {
+    pub pos_scale: Vec3,
+    pub pos_offset: Vec3,
+    pub tc_scale: Vec2,
+    pub tc_offset: Vec2,
+}
+
+impl Default for Dequantize {
+    fn default() -> Self {
+        Self {
+            pos_scale: Vec3::ONE,
+            pos_offset: Vec3::ZERO,
+            tc_scale: Vec2::ONE,
+            tc_offset: Vec2::ZERO,
+        }
+    }
+}
+
+impl Dequantize {
+    /// Turns packed positions into model space, to be multiplied onto the right of each joint
+    /// matrix
+    pub fn pos_matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.pos_offset) * Mat4::from_scale(self.pos_scale)
+    }
+
+    /// Scale in `xy` and offset in `zw` for the shader to apply to texture coordinates
+    pub fn uv_transform(&self) -> Vec4 {
+        self.tc_scale
+            .extend(self.tc_offset.x)
+            .extend(self.tc_offset.y)
+    }
+}
+
+/// The largest difference between some vertices and their packed versions
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QuantizationError {
+    /// Distance, in model units
+    pub pos: f32,
+    /// Angle, in radians
+    pub norm: f32,
+    pub tc: f32,
+    pub weight: f32,
+}
+
+/// Packs `verts`, fitting the positions and texture coordinates to the range they cover
+pub fn pack(verts: &[Vertex]) -> (Vec<PackedVertex>, Dequantize) {
+    if verts.is_empty() {
+        return (Vec::new(), Dequantize::default());
+    }
+
+    let (min_pos, max_pos) = verts.iter().fold((Vec3::MAX, Vec3::MIN), |(min, max), v| {
+        (min.min(v.pos), max.max(v.pos))
+    });
+    let (min_tc, max_tc) = verts.iter().fold((Vec2::MAX, Vec2::MIN), |(min, max), v| {
+        (min.min(v.tc), max.max(v.tc))
+    });
+
+    // Each range maps onto -i16::MAX..=i16::MAX, and a range of zero still needs a usable scale
+    let steps = i16::MAX as f32 * 2.0;
+    let pos_offset = (min_pos + max_pos) * 0.5;
+    let pos_scale = ((max_pos - min_pos) / steps).max(Vec3::splat(f32::EPSILON));
+    let tc_offset = (min_tc + max_tc) * 0.5;
+    let tc_scale = ((max_tc - min_tc) / steps).max(Vec2::splat(f32::EPSILON));
+    let dequantize = Dequantize {
+        pos_scale,
+        pos_offset,
+        tc_scale,
+        tc_offset,
+    };
+
+    let packed = verts
+        .iter()
+        .map(|v| PackedVertex {
+            pos: quantize_i16(((v.pos - pos_offset) / pos_scale).round().as_ivec3()),
+            tc: {
+                let tc = ((v.tc - tc_offset) / tc_scale).round().as_ivec2();
+                let [x, y, _] = quantize_i16(tc.extend(0));
+                [x, y]
+            },
+            norm: (v.norm.normalize_or_zero() / NORMAL_SCALE)
+                .round()
+                .as_ivec3()
+                .clamp(IVec3::splat(-127), IVec3::splat(127))
+                .to_array()
+                .map(|n| n as i8),
+            joints: v.joints,
+            weights: pack_weights(v.weights),
+        })
+        .collect();
+
+    (packed, dequantize)
+}
+
+fn quantize_i16(v: IVec3) -> [i16; 3] {
+    v.clamp(
+        IVec3::splat(-i16::MAX as i32),
+        IVec3::splat(i16::MAX as i32),
+    )
+    .to_array()
+    .map(|n| n as i16)
+}
+
+/// Scales `weights` so they add up to 1, so skinning doesn't move the vertex towards or away from
+/// the origin. Weights that are all 0 give the vertex entirely to its first joint.
+pub fn normalize_weights(weights: Vec3) -> Vec3 {
+    let total = weights.x + weights.y + weights.z;
+    if total > 0.0 {
+        weights / total
+    } else {
+        Vec3::X
+    }
+}
+
+/// Rounds weights to bytes, keeping their total at 255 so the vertex doesn't shrink towards the
+/// origin when skinned
+fn pack_weights(weights: Vec3) -> [u8; 3] {
+    let mut packed = (normalize_weights(weights) / WEIGHT_SCALE)
+        .round()
+        .clamp(Vec3::ZERO, Vec3::splat(255.0))
+        .to_array()
+        .map(|w| w as u8);
+    let largest = (0..3).max_by_key(|i| packed[*i]).expect("Not empty");
+    let others: u8 = (0..3).filter(|i| *i != largest).map(|i| packed[i]).sum();
+    packed[largest] = 255 - others;
+    packed
+}
+
+impl PackedVertex {
+    pub fn unpack(&self, dequantize: &Dequantize) -> Vertex {
+        let pos = IVec3::from_array(self.pos.map(i32::from)).as_vec3();
+        let tc = IVec2::from_array(self.tc.map(i32::from)).as_vec2();
+        let norm = IVec3::from_array(self.norm.map(i32::from)).as_vec3();
+        let weights = IVec3::from_array(self.weights.map(i32::from)).as_vec3();
+        Vertex {
+            pos: pos * dequantize.pos_scale + dequantize.pos_offset,
+            norm: norm * NORMAL_SCALE,
+            tc: tc * dequantize.tc_scale + dequantize.tc_offset,
+            joints: self.joints,
+            weights: weights * WEIGHT_SCALE,
+        }
+    }
+}
+
+/// How far `packed` is from the `original` vertices it was packed from
+pub fn quantization_error(
+    original: &[Vertex],
+    packed: &[PackedVertex],
+    dequantize: &Dequantize,
+) -> QuantizationError {
+    original
+        .iter()
+        .zip(packed)
+        .fold(QuantizationError::default(), |error, (o, p)| {
+            let u = p.unpack(dequantize);
+            let norm = match (o.norm.try_normalize(), u.norm.try_normalize()) {
+                (Some(a), Some(b)) => a.dot(b).clamp(-1.0, 1.0).acos(),
+                _ => 0.0,
+            };
+            let total = o.weights.x + o.weights.y + o.weights.z;
+            let weights = if total > 0.0 {
+                o.weights / total
+            } else {
+                Vec3::X
+            };
+            QuantizationError {
+                pos: error.pos.max(o.pos.distance(u.pos)),
+                norm: error.norm.max(norm),
+                tc: error.tc.max((o.tc - u.tc).abs().max_element()),
+                weight: error.weight.max((weights - u.weights).abs().max_element()),
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packing_stays_close_to_original() {
+        let verts = [
+            Vertex {
+                pos: Vec3::new(-2.0, 0.0, 1.0),
+                norm: Vec3::Y,
+                tc: Vec2::new(0.0, 1.0),
+                joints: [0, 1, 2],
+                weights: Vec3::new(0.333, 0.333, 0.334),
+            },
+            Vertex {
+                pos: Vec3::new(3.0, 5.0, 1.0),
+                norm: Vec3::new(1.0, 1.0, 0.0).normalize(),
+                tc: Vec2::new(2.0, -1.0),
+                ..Default::default()
+            },
+        ];
+
+        let (packed, dequantize) = pack(&verts);
+        assert_eq!(
+            packed[0].weights.iter().map(|w| *w as u32).sum::<u32>(),
+            255
+        );
+
+        let error = quantization_error(&verts, &packed, &dequantize);
+        assert!(error.pos < 1e-3, "{error:?}");
+        assert!(error.norm < 0.01, "{error:?}");
+        assert!(error.tc < 1e-3, "{error:?}");
+        assert!(error.weight <= WEIGHT_SCALE, "{error:?}");
+
+        // Joint matrices with the dequantization folded in give the original position
+        let pos = IVec3::from_array(packed[1].pos.map(i32::from)).as_vec3();
+        let restored = dequantize.pos_matrix().transform_point3(pos);
+        assert!(restored.abs_diff_eq(verts[1].pos, 1e-3));
+    }
+
+    #[test]
+    fn full_and_packed_weights_match() {
+        let weights = normalize_weights(Vec3::new(0.5, 0.5, 0.0));
+        assert_eq!(weights, Vec3::new(0.5, 0.5, 0.0));
+        assert_eq!(normalize_weights(Vec3::new(2.0, 1.0, 1.0)).x, 0.5);
+        assert_eq!(normalize_weights(Vec3::ZERO), Vec3::X);
+
+        let packed = pack_weights(weights);
+        let unpacked = IVec3::from_array(packed.map(i32::from)).as_vec3() * WEIGHT_SCALE;
+        assert!(unpacked.abs_diff_eq(weights, WEIGHT_SCALE));
+    }
+}
@@ -0,0 +1,271 @@
+//! Splitting meshes into parts that fit the limits of the GPU: few enough vertices for 16 bit
+//! indices, and for skinned meshes, only as many joints as the vertex shader has room for, letting
+//! skeletons have more joints than that.
+//!
+//! Each part of a skinned mesh has a palette mapping the joint indices of its vertices to joints
+//! of the skeleton, and only the palette's joint matrices are given to the shader when it's drawn.
+
+use std::collections::{HashMap, HashSet};
+
+use glam::Mat4;
+
+use crate::{morph::MorphTarget, Mesh, Vertex};
+
+/// How many joint matrices fit in the vertex shader's `jointTransforms` uniform
+pub const MAX_PALETTE_JOINTS: usize = 18;
+/// How many vertices can be used with 16 bit indices
+pub const MAX_INDEXED_VERTICES: usize = 1 << 16;
+
+/// The joint matrices to give the shader for a mesh with `palette`, taken from `joints`, the
+/// matrices for the whole skeleton. An empty palette uses the skeleton's joints directly.
+pub fn palette_joints(palette: &[u8], joints: &[Mat4]) -> Vec<Mat4> {
+    if palette.is_empty() {
+        return joints.to_vec();
+    }
+    palette.iter().map(|j| joints[*j as usize]).collect()
+}
+
+/// The skeleton joints that actually move a vertex
+fn influences(v: &Vertex) -> impl Iterator<Item = u8> + '_ {
+    v.joints
+        .iter()
+        .zip(v.weights.to_array())
+        .filter(|(_, w)| *w > 0.0)
+        .map(|(j, _)| *j)
+}
+
+/// Splits `mesh` into parts whose triangles only use `max_joints` joints between them, or returns
+/// it as is if its joint indices already fit. Vertices used by more than one part are copied
+/// into each.
+///
+/// Triangles are added to a part in order, skipping any that would take it over the limit, which
+/// are left for the next part.
+pub fn partition(mesh: &Mesh, max_joints: usize) -> Vec<Mesh> {
+    assert!(max_joints >= 9, "A single triangle can use up to 9 joints");
+    let global = |v: &Vertex| -> Vec<u8> {
+        influences(v)
+            .map(|j| mesh.palette.get(j as usize).copied().unwrap_or(j))
+            .collect()
+    };
+    if mesh
+        .verts
+        .iter()
+        .flat_map(&global)
+        .all(|j| (j as usize) < max_joints)
+    {
+        return vec![mesh.clone()];
+    }
+
+    let mut remaining: Vec<[u32; 3]> = mesh
+        .inds
+        .chunks_exact(3)
+        .map(|t| [t[0], t[1], t[2]].map(u32::from))
+        .collect();
+    let mut parts = Vec::new();
+
+    while !remaining.is_empty() {
+        let mut palette: Vec<u8> = Vec::new();
+        let mut triangles = Vec::new();
+        let mut skipped = Vec::new();
+
+        for triangle in remaining {
+            let mut joints = palette.clone();
+            for j in triangle
+                .iter()
+                .flat_map(|i| global(&mesh.verts[*i as usize]))
+            {
+                if !joints.contains(&j) {
+                    joints.push(j);
+                }
+            }
+            if joints.len() <= max_joints {
+                palette = joints;
+                triangles.push(triangle);
+            } else {
+                skipped.push(triangle);
+            }
+        }
+
+        let local: HashMap<u8, u8> = palette
+            .iter()
+            .enumerate()
+            .map(|(i, j)| (*j, i as u8))
+            .collect();
+        let mut part = sub_mesh(mesh, &triangles);
+        for v in &mut part.verts {
+            for (joint, weight) in v.joints.iter_mut().zip(v.weights.to_array()) {
+                let global = mesh.palette.get(*joint as usize).copied().unwrap_or(*joint);
+                // Joints without any weight don't matter, but still have to be in the palette
+                *joint = if weight > 0.0 { local[&global] } else { 0 };
+            }
+        }
+        part.palette = palette;
+        parts.push(part);
+        remaining = skipped;
+    }
+
+    parts
+}
+
+/// Splits the triangles of `inds`, which index into `mesh`'s vertices instead of its own
+/// indices, into parts with at most `max_verts` vertices. Vertices used by more than one part are
+/// copied into each.
+pub fn split_indices(mesh: &Mesh, inds: &[u32], max_verts: usize) -> Vec<Mesh> {
+    assert!(max_verts >= 3, "A triangle needs 3 vertices");
+    let mut parts = Vec::new();
+    let mut triangles = Vec::new();
+    let mut used = HashSet::new();
+
+    for t in inds.chunks_exact(3) {
+        let new = t.iter().filter(|i| !used.contains(*i)).count();
+        if used.len() + new > max_verts {
+            parts.push(sub_mesh(mesh, &triangles));
+            triangles.clear();
+            used.clear();
+        }
+        used.extend(t.iter().copied());
+        triangles.push([t[0], t[1], t[2]]);
+    }
+    if !triangles.is_empty() || parts.is_empty() {
+        parts.push(sub_mesh(mesh, &triangles));
+    }
+
+    parts
+}
+
+/// A mesh made of `triangles` from `mesh`, with only the vertices they use
+fn sub_mesh(mesh: &Mesh, triangles: &[[u32; 3]]) -> Mesh {
+    // Map from index in `mesh` to index in the part
+    let mut vertex_map: HashMap<u32, u16> = HashMap::new();
+    let mut old_indices = Vec::new();
+    let mut inds = Vec::with_capacity(triangles.len() * 3);
+    for i in triangles.iter().flatten() {
+        let new = *vertex_map.entry(*i).or_insert_with(|| {
+            old_indices.push(*i as usize);
+            (old_indices.len() - 1) as u16
+        });
+        inds.push(new);
+    }
+
+    let verts = old_indices.iter().map(|i| mesh.verts[*i]).collect();
+    let pick = |values: &[glam::Vec3]| -> Vec<glam::Vec3> {
+        if values.is_empty() {
+            return Vec::new();
+        }
+        old_indices.iter().map(|i| values[*i]).collect()
+    };
+
+    Mesh {
+        verts,
+        inds,
+        material: mesh.material,
+        format: mesh.format,
+        bounds: mesh.bounds.clone(),
+        morph_targets: mesh
+            .morph_targets
+            .iter()
+            .map(|t| MorphTarget {
+                name: t.name.clone(),
+                positions: pick(&t.positions),
+                normals: pick(&t.normals),
+            })
+            .collect(),
+        morph_weights: mesh.morph_weights.clone(),
+        palette: mesh.palette.clone(),
+        topology: mesh.topology,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    #[test]
+    fn parts_stay_within_palette() {
+        // Quads each bound to their own joint, with the joint's index in `x`
+        let mut verts = Vec::new();
+        let mut inds = Vec::new();
+        for quad in 0..30u16 {
+            for corner in 0..4u16 {
+                verts.push(Vertex {
+                    pos: Vec3::new(quad as f32, (corner % 2) as f32, (corner / 2) as f32),
+                    joints: [quad as u8, 0, 0],
+                    weights: Vec3::X,
+                    ..Default::default()
+                });
+            }
+            let first = quad * 4;
+            inds.extend([first, first + 1, first + 2, first + 1, first + 3, first + 2]);
+        }
+        let mesh = Mesh {
+            verts,
+            inds,
+            ..Default::default()
+        };
+
+        let parts = partition(&mesh, MAX_PALETTE_JOINTS);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts.iter().map(|p| p.inds.len()).sum::<usize>(), 30 * 6);
+
+        let skeleton: Vec<Mat4> = (0..30)
+            .map(|i| Mat4::from_translation(Vec3::Z * i as f32 * 10.0))
+            .collect();
+        for part in &parts {
+            assert!(part.palette.len() <= MAX_PALETTE_JOINTS);
+            let joints = palette_joints(&part.palette, &skeleton);
+            for v in &part.verts {
+                // Skinned by the palette, each vertex is still moved by its original joint
+                assert_eq!(v.skinned_pos(&joints).z - v.pos.z, v.pos.x * 10.0);
+            }
+        }
+    }
+
+    #[test]
+    fn splits_by_vertex_count() {
+        // A strip of 20 quads sharing their edges, so 42 vertices
+        let verts: Vec<Vertex> = (0..42)
+            .map(|i| Vertex {
+                pos: Vec3::new((i / 2) as f32, (i % 2) as f32, 0.0),
+                ..Default::default()
+            })
+            .collect();
+        let inds: Vec<u32> = (0..20)
+            .flat_map(|q| {
+                let first = q * 2;
+                [first, first + 1, first + 2, first + 1, first + 3, first + 2]
+            })
+            .collect();
+        let mesh = Mesh {
+            verts,
+            material: 3,
+            ..Default::default()
+        };
+
+        let parts = split_indices(&mesh, &inds, 16);
+        // 7 quads fit in 16 vertices, and the edge between parts is copied into both
+        assert_eq!(parts.len(), 3);
+        assert_eq!(
+            parts.iter().map(|p| p.verts.len()).sum::<usize>(),
+            42 + 2 * 2
+        );
+        let triangles = parts.iter().flat_map(|p| {
+            p.inds.chunks_exact(3).map(|t| {
+                t.iter()
+                    .map(|i| p.verts[*i as usize].pos)
+                    .collect::<Vec<_>>()
+            })
+        });
+        for (part, original) in triangles.zip(inds.chunks_exact(3)) {
+            assert!(
+                part.len() == 3
+                    && part
+                        .iter()
+                        .zip(original)
+                        .all(|(p, i)| *p == mesh.verts[*i as usize].pos)
+            );
+        }
+        assert!(parts.iter().all(|p| p.verts.len() <= 16 && p.material == 3));
+    }
+}
@@ -0,0 +1,280 @@
+use crate::{root_motion::combine_deltas, Animation, JointTransform, Marker, Skeleton};
+
+/// What an [`AnimationPlayer`] does when it reaches either end of its animation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlaybackMode {
+    /// Jumps back to the other end and keeps playing
+    #[default]
+    Loop,
+    /// Stops at the end and reports that it has finished
+    Once,
+    /// Reverses direction and keeps playing back and forth
+    PingPong,
+    /// Holds at the end without finishing, so changing the speed (e.g. reversing) continues
+    /// playback from there
+    Clamp,
+}
+
+/// Keeps track of the playback time of an animation
+#[derive(Clone, Debug)]
+pub struct AnimationPlayer {
+    /// Index into the model's animations
+    pub animation: usize,
+    pub mode: PlaybackMode,
+    /// Multiplier for how fast time advances, negative values play in reverse
+    pub speed: f32,
+    duration: f32,
+    time: f32,
+    /// 1 or -1 depending on which way a ping-pong animation is currently playing
+    direction: f32,
+    finished: bool,
+    /// The ranges of time played during the last update, in the order they were played
+    played: Vec<(f32, f32)>,
+}
+
+impl AnimationPlayer {
+    /// Creates a player for `animation`, which is at index `index` in the model's animations
+    pub fn new(index: usize, animation: &Animation, mode: PlaybackMode) -> Self {
+        AnimationPlayer {
+            animation: index,
+            mode,
+            speed: 1.0,
+            duration: animation.duration(),
+            time: 0.0,
+            direction: 1.0,
+            finished: false,
+            played: Vec::new(),
+        }
+    }
+
+    /// The current time in the animation, between 0 and its duration
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    /// The current time as a fraction of the duration
+    pub fn progress(&self) -> f32 {
+        if self.duration > 0.0 {
+            self.time / self.duration
+        } else {
+            0.0
+        }
+    }
+
+    /// Whether a [`PlaybackMode::Once`] animation has reached the end.
+    /// Always false for the other modes.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Jumps to `time`, clamped to the duration of the animation. Doesn't report any markers.
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.clamp(0.0, self.duration);
+        self.finished = false;
+        self.played.clear();
+    }
+
+    /// Advances the animation by `dt` seconds multiplied by the speed
+    pub fn update(&mut self, dt: f32) {
+        self.played.clear();
+
+        let delta = dt * self.speed;
+        if self.finished || self.duration <= 0.0 || delta == 0.0 {
+            return;
+        }
+
+        let mut step = delta.signum() * self.direction;
+        let mut remaining = delta.abs();
+
+        // Skip whole cycles so a huge time step can't loop for too long
+        if matches!(self.mode, PlaybackMode::Loop | PlaybackMode::PingPong) {
+            remaining %= 2.0 * self.duration;
+        }
+
+        while remaining > 0.0 {
+            let end = if step > 0.0 { self.duration } else { 0.0 };
+            let to_end = (end - self.time).abs();
+
+            if remaining < to_end {
+                let time = self.time + remaining * step;
+                self.played.push((self.time, time));
+                self.time = time;
+                break;
+            }
+
+            self.played.push((self.time, end));
+            remaining -= to_end;
+
+            match self.mode {
+                PlaybackMode::Loop => self.time = self.duration - end,
+                PlaybackMode::PingPong => {
+                    self.time = end;
+                    self.direction = -self.direction;
+                    step = -step;
+                }
+                PlaybackMode::Once => {
+                    self.time = end;
+                    self.finished = true;
+                    break;
+                }
+                PlaybackMode::Clamp => {
+                    self.time = end;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Samples the animation at the current time
+    pub fn sample(&self, animation: &Animation, skeleton: &Skeleton) -> Vec<JointTransform> {
+        animation.sample(self.time, skeleton)
+    }
+
+    /// The root motion over the last update, relative to the entity's transform before the
+    /// update. Returns no motion if the animation doesn't have any root motion.
+    pub fn root_motion(&self, animation: &Animation) -> JointTransform {
+        let Some(root_motion) = &animation.root_motion else {
+            return JointTransform::default();
+        };
+
+        self.played
+            .iter()
+            .fold(JointTransform::default(), |total, (from, to)| {
+                combine_deltas(&total, &root_motion.delta(*from, *to))
+            })
+    }
+
+    /// Every marker passed during the last update, in the order they were passed,
+    /// including any passed while looping or bouncing back
+    pub fn crossed_markers<'a>(&self, animation: &'a Animation) -> Vec<&'a Marker> {
+        self.played
+            .iter()
+            .flat_map(|(from, to)| {
+                let mut crossed = animation.crossed_markers(*from, *to);
+                // Looping jumps away from the end and finishing stops there, so markers on the
+                // end won't start the next range and are reported on the way to it instead
+                let reached_end = (*to == self.duration && to > from) || (*to == 0.0 && to < from);
+                if reached_end && matches!(self.mode, PlaybackMode::Loop | PlaybackMode::Once) {
+                    crossed.extend(animation.markers.iter().filter(|m| m.time == *to));
+                }
+                crossed
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::{Interpolation, JointAnimation, Track};
+
+    fn animation() -> Animation {
+        Animation {
+            joints: vec![JointAnimation {
+                translations: Track::new(
+                    Interpolation::Linear,
+                    vec![(0.0, Vec3::ZERO), (2.0, Vec3::X)],
+                ),
+                ..Default::default()
+            }],
+            markers: vec![Marker {
+                name: "step".to_string(),
+                time: 0.5,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn loop_wraps_and_reports_markers() {
+        let anim = animation();
+        let mut player = AnimationPlayer::new(0, &anim, PlaybackMode::Loop);
+
+        player.update(1.0);
+        assert_eq!(player.crossed_markers(&anim).len(), 1);
+        player.update(1.75);
+        assert!((player.time() - 0.75).abs() < 1e-6);
+        assert_eq!(player.crossed_markers(&anim).len(), 1);
+
+        player.speed = -1.0;
+        player.update(1.0);
+        assert!((player.time() - 1.75).abs() < 1e-6);
+        assert_eq!(player.crossed_markers(&anim).len(), 1);
+        assert!(!player.is_finished());
+    }
+
+    #[test]
+    fn once_finishes_and_holds() {
+        let anim = animation();
+        let mut player = AnimationPlayer::new(0, &anim, PlaybackMode::Once);
+
+        player.update(3.0);
+        assert!(player.is_finished());
+        assert_eq!(player.time(), 2.0);
+
+        player.seek(1.0);
+        assert!(!player.is_finished());
+    }
+
+    #[test]
+    fn reports_markers_on_the_end() {
+        let mut anim = animation();
+        anim.markers.push(Marker {
+            name: "land".to_string(),
+            time: 2.0,
+        });
+        let names = |player: &AnimationPlayer| {
+            player
+                .crossed_markers(&anim)
+                .iter()
+                .map(|m| m.name.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        let mut player = AnimationPlayer::new(0, &anim, PlaybackMode::Loop);
+        player.update(1.0);
+        player.update(1.75);
+        assert_eq!(names(&player), ["land", "step"]);
+        // Back across the end while playing in reverse
+        player.speed = -1.0;
+        player.update(1.0);
+        assert_eq!(names(&player), ["step", "land"]);
+
+        let mut player = AnimationPlayer::new(0, &anim, PlaybackMode::Once);
+        player.update(1.0);
+        player.update(1.0);
+        assert!(player.is_finished());
+        assert_eq!(names(&player), ["land"]);
+    }
+
+    #[test]
+    fn ping_pong_bounces() {
+        let anim = animation();
+        let mut player = AnimationPlayer::new(0, &anim, PlaybackMode::PingPong);
+
+        player.update(2.5);
+        assert!((player.time() - 1.5).abs() < 1e-6);
+        player.update(2.0);
+        assert!((player.time() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn clamp_holds_without_finishing() {
+        let anim = animation();
+        let mut player = AnimationPlayer::new(0, &anim, PlaybackMode::Clamp);
+
+        player.update(5.0);
+        assert_eq!(player.time(), 2.0);
+        assert!(!player.is_finished());
+
+        player.speed = -1.0;
+        player.update(0.5);
+        assert!((player.time() - 1.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,222 @@
+//! Casting rays against meshes on the CPU, for picking things on screen and line of sight checks.
+
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+use crate::{bounds::Aabb, optimize::triangle_list, partition::palette_joints, Mesh, Vertex};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    /// Always normalized, so distances along the ray are in the same units as the meshes
+    pub dir: Vec3,
+}
+
+/// Where a ray hit one of the triangles of a mesh
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    /// Index of the mesh in the slice given to [`Ray::cast`], or 0 for [`Ray::cast_mesh`]
+    pub mesh: usize,
+    /// Index of the triangle, so its vertices are `inds[triangle * 3..triangle * 3 + 3]`. For
+    /// strips it's the index in the list from [`triangle_list`].
+    pub triangle: usize,
+    /// From the ray's origin
+    pub distance: f32,
+    /// How much each of the triangle's vertices contributes to the hit point, which can be used
+    /// to interpolate anything else about them like the texture coordinates
+    pub barycentric: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, dir: Vec3) -> Ray {
+        Ray {
+            origin,
+            dir: dir.normalize(),
+        }
+    }
+
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.dir * distance
+    }
+
+    /// The ray from the camera through `point` on the screen, in pixels from the top left of a
+    /// screen that's `size` pixels big. See [`Ray::from_ndc`].
+    pub fn from_screen(point: Vec2, size: Vec2, matrix: &Mat4) -> Ray {
+        let ndc = Vec2::new(point.x / size.x * 2.0 - 1.0, 1.0 - point.y / size.y * 2.0);
+        Ray::from_ndc(ndc, matrix)
+    }
+
+    /// The ray from the camera through `ndc`, from -1 to 1 in each axis with `y` pointing up.
+    /// `matrix` is a perspective projection, or a projection multiplied by view and model matrices
+    /// to get the ray in that model's space. Any clip space depth range works.
+    pub fn from_ndc(ndc: Vec2, matrix: &Mat4) -> Ray {
+        let inverse = matrix.inverse();
+        // The camera is the only point with a clip space `x`, `y` and `w` of 0
+        let eye = inverse * Vec4::Z;
+        let eye = eye.truncate() / eye.w;
+        let point = inverse * ndc.extend(0.0).extend(1.0);
+        // Depending on the depth range, the point can be behind the camera, which is when its
+        // clip space `w` is negative
+        let dir = (point.truncate() / point.w - eye) * point.w.signum();
+        Ray::new(eye, dir)
+    }
+
+    /// Distance to where the ray enters `aabb`, or 0 if it starts inside
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let inverse = self.dir.recip();
+        let a = (aabb.min - self.origin) * inverse;
+        let b = (aabb.max - self.origin) * inverse;
+        // NaN from a zero direction and an origin on the box's edge is ignored by min and max
+        let enter = a.min(b).max_element().max(0.0);
+        let exit = a.max(b).min_element();
+        (enter <= exit).then_some(enter)
+    }
+
+    /// Distance to where the ray hits the triangle from either side, and the barycentric
+    /// coordinates of the hit
+    pub fn intersect_triangle(&self, a: Vec3, b: Vec3, c: Vec3) -> Option<(f32, Vec3)> {
+        let ab = b - a;
+        let ac = c - a;
+        let p = self.dir.cross(ac);
+        let det = ab.dot(p);
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let to_origin = self.origin - a;
+        let u = to_origin.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = to_origin.cross(ab);
+        let v = self.dir.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = ac.dot(q) * inv_det;
+        (distance >= 0.0).then_some((distance, Vec3::new(1.0 - u - v, u, v)))
+    }
+
+    /// The closest hit on the triangles of a mesh, with `inds` as a triangle list. With `joints`
+    /// from [`crate::Skeleton::apply_pose_to_joints`] the mesh is skinned first, otherwise it's
+    /// tested in its bind pose.
+    pub fn cast_mesh(
+        &self,
+        verts: &[Vertex],
+        inds: &[u16],
+        joints: Option<&[Mat4]>,
+    ) -> Option<Hit> {
+        let positions: Vec<Vec3> = match joints {
+            Some(joints) => verts.iter().map(|v| v.skinned_pos(joints)).collect(),
+            None => verts.iter().map(|v| v.pos).collect(),
+        };
+
+        inds.chunks_exact(3)
+            .enumerate()
+            .filter_map(|(triangle, tri)| {
+                let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| positions[i as usize]);
+                let (distance, barycentric) = self.intersect_triangle(a, b, c)?;
+                Some(Hit {
+                    mesh: 0,
+                    triangle,
+                    distance,
+                    barycentric,
+                })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    /// The closest hit on any of `meshes`, skipping meshes whose bounds the ray misses. See
+    /// [`Ray::cast_mesh`], but `joints` is for the whole skeleton and is mapped through each
+    /// mesh's palette.
+    pub fn cast(&self, meshes: &[Mesh], joints: Option<&[Mat4]>) -> Option<Hit> {
+        meshes
+            .iter()
+            .enumerate()
+            .filter(|(_, mesh)| {
+                let aabb = match joints {
+                    Some(joints) => mesh.bounds.posed(joints),
+                    None => mesh.bounds.aabb,
+                };
+                self.intersect_aabb(&aabb).is_some()
+            })
+            .filter_map(|(index, mesh)| {
+                let palette = joints.map(|joints| palette_joints(&mesh.palette, joints));
+                let inds = triangle_list(&mesh.inds, mesh.topology);
+                let hit = self.cast_mesh(&mesh.verts, &inds, palette.as_deref())?;
+                Some(Hit { mesh: index, ..hit })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounds::MeshBounds;
+
+    #[test]
+    fn ray_from_screen_goes_through_point() {
+        let proj = Mat4::perspective_rh(60f32.to_radians(), 2.0, 0.1, 10.0);
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 1.0, 5.0), Vec3::ZERO, Vec3::Y);
+        let matrix = proj * view;
+
+        let target = Vec3::new(0.5, 0.25, -1.0);
+        let clip = matrix * target.extend(1.0);
+        let ndc = clip.truncate().truncate() / clip.w;
+        let size = Vec2::new(400.0, 200.0);
+        let screen = Vec2::new((ndc.x + 1.0) * 0.5, (1.0 - ndc.y) * 0.5) * size;
+
+        for matrix in [
+            matrix,
+            Mat4::perspective_rh_gl(60f32.to_radians(), 2.0, 0.1, 10.0) * view,
+        ] {
+            let ray = Ray::from_screen(screen, size, &matrix);
+            assert!(ray.origin.abs_diff_eq(Vec3::new(0.0, 1.0, 5.0), 1e-4));
+            let distance = ray.origin.distance(target);
+            assert!(ray.at(distance).abs_diff_eq(target, 1e-3), "{ray:?}");
+        }
+    }
+
+    #[test]
+    fn hits_closest_triangle() {
+        let vertex = |x: f32, y: f32, z: f32, joint: u8| Vertex {
+            pos: Vec3::new(x, y, z),
+            joints: [joint, 0, 0],
+            ..Default::default()
+        };
+        // A triangle at z = 0 on joint 0, and one in front of it at z = 1 on joint 1
+        let verts = vec![
+            vertex(-1.0, -1.0, 0.0, 0),
+            vertex(1.0, -1.0, 0.0, 0),
+            vertex(0.0, 1.0, 0.0, 0),
+            vertex(-1.0, -1.0, 1.0, 1),
+            vertex(1.0, -1.0, 1.0, 1),
+            vertex(0.0, 1.0, 1.0, 1),
+        ];
+        let mesh = Mesh {
+            bounds: MeshBounds::from_vertices(&verts),
+            verts,
+            inds: vec![0, 1, 2, 3, 4, 5],
+            ..Default::default()
+        };
+        let meshes = [mesh];
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z);
+
+        let hit = ray.cast(&meshes, None).unwrap();
+        assert_eq!(hit.triangle, 1);
+        assert!((hit.distance - 4.0).abs() < 1e-5);
+        assert!(hit
+            .barycentric
+            .abs_diff_eq(Vec3::new(0.25, 0.25, 0.5), 1e-5));
+
+        // Moving joint 1 out of the way leaves the triangle behind it
+        let joints = [Mat4::IDENTITY, Mat4::from_translation(Vec3::X * 10.0)];
+        let hit = ray.cast(&meshes, Some(&joints)).unwrap();
+        assert_eq!(hit.triangle, 0);
+
+        let miss = Ray::new(Vec3::new(3.0, 0.0, 5.0), Vec3::NEG_Z);
+        assert_eq!(miss.cast(&meshes, None), None);
+    }
+}
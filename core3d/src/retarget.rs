@@ -0,0 +1,176 @@
+use crate::{Animation, JointAnimation, JointTransform, Skeleton};
+
+/// Maps animations made for one skeleton onto another by matching joint names, so one set of
+/// animations can be shared between characters with different proportions.
+#[derive(Clone, Debug)]
+pub struct Retargeter {
+    /// For each joint in the target skeleton, the index of the source joint it copies
+    map: Vec<Option<usize>>,
+    source_base: Vec<JointTransform>,
+    target_base: Vec<JointTransform>,
+}
+
+impl Retargeter {
+    /// Matches joints in `target` to joints in `source` with the same name. `aliases` are pairs
+    /// of (source name, target name) for joints that are named differently between the two.
+    pub fn new(source: &Skeleton, target: &Skeleton, aliases: &[(&str, &str)]) -> Self {
+        let map = target
+            .joints
+            .iter()
+            .map(|joint| {
+                let source_name = aliases
+                    .iter()
+                    .find(|(_, target_name)| *target_name == joint.name)
+                    .map_or(joint.name.as_str(), |(source_name, _)| source_name);
+                source.joint_index(source_name)
+            })
+            .collect();
+
+        Retargeter {
+            map,
+            source_base: source.base_pose(),
+            target_base: target.base_pose(),
+        }
+    }
+
+    /// For each joint in the target skeleton, the index of the source joint it copies
+    pub fn mapping(&self) -> &[Option<usize>] {
+        &self.map
+    }
+
+    /// Indices of joints in the target skeleton that have no matching source joint
+    pub fn unmapped(&self) -> Vec<usize> {
+        (0..self.map.len())
+            .filter(|i| self.map[*i].is_none())
+            .collect()
+    }
+
+    /// How much to scale the translation of a joint by, based on how far the joint is from its
+    /// parent in each skeleton
+    fn length_ratio(source: &JointTransform, target: &JointTransform) -> f32 {
+        let source_len = source.pos.length();
+        if source_len > 1e-6 {
+            target.pos.length() / source_len
+        } else {
+            1.0
+        }
+    }
+
+    /// Creates a copy of `animation` for the target skeleton. Rotations are applied relative to
+    /// each skeleton's base pose, and translations are scaled by the difference in bone length.
+    /// Target joints without a matching source joint are left in their base pose.
+    pub fn retarget(&self, animation: &Animation) -> Animation {
+        let joints = self
+            .map
+            .iter()
+            .enumerate()
+            .map(|(i, source)| {
+                let Some(source_joint) = source.and_then(|s| animation.joints.get(s)) else {
+                    return JointAnimation::default();
+                };
+                let source_base = self.source_base[source.expect("Checked above")];
+                let target_base = self.target_base[i];
+                let ratio = Self::length_ratio(&source_base, &target_base);
+
+                let mut joint = source_joint.clone();
+                if animation.additive {
+                    // Already differences from a reference pose, so only the length changes
+                    joint.translations.map_values(|v| v * ratio, |t| t * ratio);
+                    return joint;
+                }
+
+                let rot_offset = target_base.rot * source_base.rot.inverse();
+                let inv_scale = source_base.scale.recip();
+                joint.translations.map_values(
+                    |v| target_base.pos + (v - source_base.pos) * ratio,
+                    |t| t * ratio,
+                );
+                joint
+                    .rotations
+                    .map_values(|v| (rot_offset * v).normalize(), |t| rot_offset * t);
+                joint.scales.map_values(
+                    |v| target_base.scale * v * inv_scale,
+                    |t| target_base.scale * t * inv_scale,
+                );
+                joint
+            })
+            .collect();
+
+        let mut root_motion = animation.root_motion.clone();
+        if let (Some(motion), Some(Some(source_root))) = (&mut root_motion, self.map.first()) {
+            let ratio = Self::length_ratio(&self.source_base[*source_root], &self.target_base[0]);
+            motion.translations.map_values(|v| v * ratio, |t| t * ratio);
+        }
+
+        Animation {
+            name: animation.name.clone(),
+            joints,
+            additive: animation.additive,
+            markers: animation.markers.clone(),
+            root_motion,
+            // These are for the source's meshes
+            morph_weights: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::{Interpolation, Joint, Track};
+
+    fn joint(name: &str, pos: Vec3, parent: Option<u8>, children: Vec<u8>) -> Joint {
+        Joint {
+            name: name.to_string(),
+            base_transform: JointTransform {
+                pos,
+                ..Default::default()
+            },
+            parent,
+            children,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn maps_by_name_and_scales_translations() {
+        let source = Skeleton {
+            joints: vec![
+                joint("Hips", Vec3::Y, None, vec![1]),
+                joint("Spine", Vec3::Y, Some(0), vec![]),
+            ],
+        };
+        // Twice as tall, with the joints in a different order and the spine renamed
+        let target = Skeleton {
+            joints: vec![
+                joint("Chest", Vec3::Y * 2.0, Some(1), vec![]),
+                joint("Hips", Vec3::Y * 2.0, None, vec![0]),
+            ],
+        };
+
+        let retargeter = Retargeter::new(&source, &target, &[("Spine", "Chest")]);
+        assert_eq!(retargeter.mapping(), &[Some(1), Some(0)]);
+
+        let animation = Animation {
+            name: "Bounce".to_string(),
+            joints: vec![
+                JointAnimation {
+                    translations: Track::new(
+                        Interpolation::Linear,
+                        vec![(0.0, Vec3::Y), (1.0, Vec3::new(0.0, 1.5, 0.0))],
+                    ),
+                    ..Default::default()
+                },
+                JointAnimation::default(),
+            ],
+            ..Default::default()
+        };
+
+        let retargeted = retargeter.retarget(&animation);
+        let hips = retargeted.joints[1].translations.sample(1.0).unwrap();
+        assert!(hips.abs_diff_eq(Vec3::new(0.0, 3.0, 0.0), 1e-6));
+        assert!(retargeted.joints[0].translations.is_empty());
+    }
+}
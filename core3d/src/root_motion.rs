@@ -0,0 +1,172 @@
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::{Animation, Interpolation, JointTransform, Track};
+
+/// Horizontal movement and turning taken out of an animation's root joint, so it can be applied
+/// to the entity playing the animation instead of making the model drift away from its origin.
+/// Both tracks are relative to the first frame of the animation.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RootMotion {
+    /// Movement along the X and Z axes in model space
+    pub translations: Track<Vec3>,
+    /// Rotation around the Y axis
+    pub rotations: Track<Quat>,
+}
+
+impl RootMotion {
+    /// The total motion at time `t` since the start of the animation
+    fn sample(&self, t: f32) -> (Vec3, Quat) {
+        (
+            self.translations.sample(t).unwrap_or(Vec3::ZERO),
+            self.rotations.sample(t).unwrap_or(Quat::IDENTITY),
+        )
+    }
+
+    /// The motion from time `from` to time `to`, relative to the entity at time `from`.
+    /// `to` may be before `from` when playing in reverse.
+    pub fn delta(&self, from: f32, to: f32) -> JointTransform {
+        let (from_pos, from_rot) = self.sample(from);
+        let (to_pos, to_rot) = self.sample(to);
+        let inv_rot = from_rot.inverse();
+
+        JointTransform {
+            pos: inv_rot * (to_pos - from_pos),
+            rot: (inv_rot * to_rot).normalize(),
+            scale: Vec3::ONE,
+        }
+    }
+}
+
+/// Combines two consecutive root motion deltas into one
+pub fn combine_deltas(first: &JointTransform, second: &JointTransform) -> JointTransform {
+    JointTransform {
+        pos: first.pos + first.rot * second.pos,
+        rot: (first.rot * second.rot).normalize(),
+        scale: Vec3::ONE,
+    }
+}
+
+/// The part of `rot` that rotates around the Y axis
+fn yaw(rot: Quat) -> Quat {
+    let twist = Quat::from_xyzw(0.0, rot.y, 0.0, rot.w);
+    if twist.length_squared() < 1e-12 {
+        // Rotated 180 degrees around a horizontal axis, so there is no meaningful yaw
+        Quat::IDENTITY
+    } else {
+        twist.normalize()
+    }
+}
+
+impl Animation {
+    /// Moves the horizontal translation and yaw of the root joint (at index `root`) out of its
+    /// tracks and into [`Animation::root_motion`]. The root joint keeps its vertical movement and
+    /// its position and yaw from the first frame.
+    pub fn extract_root_motion(&mut self, root: usize) {
+        let Some(joint) = self.joints.get_mut(root) else {
+            return;
+        };
+        let mut motion = RootMotion::default();
+
+        // Translation
+        let translations = &mut joint.translations;
+        if let Some(&(_, start)) = translations.keys.first() {
+            let horizontal = |v: Vec3| Vec3::new(v.x, 0.0, v.z);
+
+            motion.translations = Track {
+                interpolation: translations.interpolation,
+                keys: translations
+                    .keys
+                    .iter()
+                    .map(|(t, v)| (*t, horizontal(*v - start)))
+                    .collect(),
+                tangents: translations
+                    .tangents
+                    .iter()
+                    .map(|(i, o)| (horizontal(*i), horizontal(*o)))
+                    .collect(),
+            };
+
+            translations.map_values(
+                |v| Vec3::new(start.x, v.y, start.z),
+                |tangent| Vec3::new(0.0, tangent.y, 0.0),
+            );
+        }
+
+        // Rotation
+        let rotations = &mut joint.rotations;
+        if let Some(&(_, start)) = rotations.keys.first() {
+            let inv_start_yaw = yaw(start).inverse();
+            let turns: Vec<Quat> = rotations
+                .keys
+                .iter()
+                .map(|(_, rot)| (yaw(*rot) * inv_start_yaw).normalize())
+                .collect();
+
+            motion.rotations = Track::new(
+                // Tangents can't be split the same way, so cubic splines are approximated
+                match rotations.interpolation {
+                    Interpolation::CubicSpline => Interpolation::Linear,
+                    interpolation => interpolation,
+                },
+                rotations
+                    .keys
+                    .iter()
+                    .zip(&turns)
+                    .map(|((t, _), turn)| (*t, *turn))
+                    .collect(),
+            );
+
+            for ((_, rot), turn) in rotations.keys.iter_mut().zip(&turns) {
+                *rot = (turn.inverse() * *rot).normalize();
+            }
+            for ((in_tangent, out_tangent), turn) in rotations.tangents.iter_mut().zip(&turns) {
+                *in_tangent = turn.inverse() * *in_tangent;
+                *out_tangent = turn.inverse() * *out_tangent;
+            }
+        }
+
+        self.root_motion = Some(motion);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        player::{AnimationPlayer, PlaybackMode},
+        JointAnimation,
+    };
+
+    #[test]
+    fn extracted_motion_accumulates_across_loops() {
+        let mut anim = Animation {
+            joints: vec![JointAnimation {
+                translations: Track::new(
+                    Interpolation::Linear,
+                    vec![
+                        (0.0, Vec3::new(0.0, 1.0, 0.0)),
+                        (2.0, Vec3::new(2.0, 1.0, 0.0)),
+                    ],
+                ),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        anim.extract_root_motion(0);
+
+        // The root joint itself should stay in place
+        assert_eq!(anim.joints[0].translations.sample(1.0), Some(Vec3::Y));
+
+        let mut player = AnimationPlayer::new(0, &anim, PlaybackMode::Loop);
+        player.update(1.5);
+        assert!(player
+            .root_motion(&anim)
+            .pos
+            .abs_diff_eq(Vec3::X * 1.5, 1e-5));
+
+        // Wraps from 1.5 back around to 0.5
+        player.update(1.0);
+        assert!(player.root_motion(&anim).pos.abs_diff_eq(Vec3::X, 1e-5));
+    }
+}
@@ -0,0 +1,224 @@
+use glam::Mat4;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bounds::{Aabb, MeshBounds},
+    partition::palette_joints,
+    JointTransform,
+};
+
+/// An object in a [`Scene`]
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Node {
+    pub name: String,
+    /// Relative to the parent node
+    pub transform: JointTransform,
+    /// Indices into [`crate::Model::meshes`], one for each part of the node's mesh, or empty if
+    /// it doesn't have one
+    pub meshes: Vec<usize>,
+    /// Whether the meshes are deformed by the skeleton. Their vertices are already in model
+    /// space, so the node's transform doesn't apply to them. Otherwise every vertex is bound to
+    /// joint 0, so the mesh can be drawn with the node's transform in place of that joint, see
+    /// [`Scene::instance_joints`].
+    pub skinned: bool,
+    /// The joint this node is, so it follows the animated skeleton instead of using `transform`
+    pub joint: Option<u8>,
+    pub parent: Option<usize>,
+    /// Indices into [`Scene::nodes`]
+    pub children: Vec<usize>,
+}
+
+/// A mesh drawn by a node of a [`Scene`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshInstance {
+    /// Index into [`crate::Model::meshes`]
+    pub mesh: usize,
+    /// Index into [`Scene::nodes`] of the node drawing the mesh, or None if no node uses it
+    pub node: Option<usize>,
+}
+
+/// The hierarchy of objects in a model
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Scene {
+    /// Sorted so parents come before their children
+    pub nodes: Vec<Node>,
+}
+
+impl Scene {
+    /// Indices of the nodes without a parent
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.nodes.len()).filter(|i| self.nodes[*i].parent.is_none())
+    }
+
+    /// Index of the first node called `name`
+    pub fn node_index(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|n| n.name == name)
+    }
+
+    /// Model space transform of every node. `joints` are the model space transforms of the
+    /// skeleton's joints in the current pose, see [`crate::Skeleton::model_space_transforms`],
+    /// and are used for nodes that are joints. Without them every node uses its own transform.
+    pub fn model_space_transforms(&self, joints: Option<&[Mat4]>) -> Vec<Mat4> {
+        let mut transforms: Vec<Mat4> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let joint = node
+                .joint
+                .and_then(|j| joints.and_then(|joints| joints.get(j as usize)));
+            let transform = match (joint, node.parent) {
+                (Some(joint), _) => *joint,
+                (None, Some(parent)) => transforms[parent] * Mat4::from(node.transform),
+                (None, None) => node.transform.into(),
+            };
+            transforms.push(transform);
+        }
+        transforms
+    }
+
+    /// Every mesh to draw out of `mesh_count` meshes, once for each node that uses it. Meshes no
+    /// node uses, like all of them in models from before there were scenes, are drawn once on
+    /// their own.
+    pub fn mesh_instances(&self, mesh_count: usize) -> Vec<MeshInstance> {
+        let mut used = vec![false; mesh_count];
+        let mut instances: Vec<MeshInstance> = Vec::new();
+        for (node, n) in self.nodes.iter().enumerate() {
+            for mesh in n.meshes.iter().copied().filter(|m| *m < mesh_count) {
+                used[mesh] = true;
+                instances.push(MeshInstance {
+                    mesh,
+                    node: Some(node),
+                });
+            }
+        }
+        instances.extend(
+            (0..mesh_count)
+                .filter(|m| !used[*m])
+                .map(|mesh| MeshInstance { mesh, node: None }),
+        );
+        instances
+    }
+
+    /// The joint matrices to draw `instance` with, for a mesh with `palette`. Skinned meshes, and
+    /// meshes without a node, use `joints`, the skeleton's matrices from
+    /// [`crate::Skeleton::apply_pose_to_joints`]. The vertices of any other mesh are all bound to
+    /// joint 0, which is given the node's transform from `transforms`, as returned by
+    /// [`Scene::model_space_transforms`].
+    pub fn instance_joints(
+        &self,
+        instance: MeshInstance,
+        palette: &[u8],
+        transforms: &[Mat4],
+        joints: &[Mat4],
+    ) -> Vec<Mat4> {
+        match instance.node {
+            Some(node) if !self.nodes[node].skinned => vec![transforms[node]],
+            _ => palette_joints(palette, joints),
+        }
+    }
+
+    /// A box around `instance` posed like [`Scene::instance_joints`] draws it, for a mesh with
+    /// `bounds`. The joint bounds are for skeleton joints even if the mesh has a palette, so
+    /// skinned meshes are posed with all of `joints` rather than their palette's.
+    pub fn instance_bounds(
+        &self,
+        instance: MeshInstance,
+        bounds: &MeshBounds,
+        transforms: &[Mat4],
+        joints: &[Mat4],
+    ) -> Aabb {
+        match instance.node {
+            Some(node) if !self.nodes[node].skinned => bounds.posed(&transforms[node..=node]),
+            _ => bounds.posed(joints),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    fn node(name: &str, pos: Vec3, joint: Option<u8>, parent: Option<usize>) -> Node {
+        Node {
+            name: name.to_string(),
+            transform: JointTransform {
+                pos,
+                ..Default::default()
+            },
+            joint,
+            parent,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn nodes_follow_joints() {
+        let scene = Scene {
+            nodes: vec![
+                node("Table", Vec3::X, None, None),
+                node("Cup", Vec3::Y, None, Some(0)),
+                node("Hand", Vec3::ZERO, Some(0), None),
+                node("Sword", Vec3::Z, None, Some(2)),
+            ],
+        };
+        assert_eq!(scene.roots().collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(scene.node_index("Sword"), Some(3));
+
+        let hand = Mat4::from_translation(Vec3::new(0.0, 5.0, 0.0));
+        let transforms = scene.model_space_transforms(Some(&[hand]));
+        let origin = |i: usize| transforms[i].transform_point3(Vec3::ZERO);
+        assert_eq!(origin(1), Vec3::new(1.0, 1.0, 0.0));
+        assert_eq!(origin(3), Vec3::new(0.0, 5.0, 1.0));
+    }
+
+    #[test]
+    fn static_meshes_use_node_transform() {
+        let mut scene = Scene {
+            nodes: vec![
+                node("Rock", Vec3::X, None, None),
+                node("Body", Vec3::ZERO, None, None),
+                node("Rock.001", Vec3::Y, None, None),
+            ],
+        };
+        // Both rocks share the same mesh
+        scene.nodes[0].meshes = vec![0];
+        scene.nodes[1].meshes = vec![1];
+        scene.nodes[1].skinned = true;
+        scene.nodes[2].meshes = vec![0];
+
+        let instances = scene.mesh_instances(3);
+        assert_eq!(
+            instances
+                .iter()
+                .map(|i| (i.mesh, i.node))
+                .collect::<Vec<_>>(),
+            [(0, Some(0)), (1, Some(1)), (0, Some(2)), (2, None)]
+        );
+
+        let transforms = scene.model_space_transforms(None);
+        let skeleton = [Mat4::from_translation(Vec3::Z), Mat4::IDENTITY];
+        let origin = |instance: MeshInstance| {
+            scene.instance_joints(instance, &[], &transforms, &skeleton)[0]
+                .transform_point3(Vec3::ZERO)
+        };
+        assert_eq!(origin(instances[0]), Vec3::X);
+        assert_eq!(origin(instances[2]), Vec3::Y);
+        assert_eq!(origin(instances[1]), Vec3::Z);
+        assert_eq!(origin(instances[3]), Vec3::Z);
+
+        // A skinned mesh's palette only has joint 1, but its bounds are still by skeleton joint
+        let bounds = |joint: u8| MeshBounds {
+            joints: vec![crate::bounds::JointBounds {
+                joint,
+                aabb: Aabb::default(),
+            }],
+            ..Default::default()
+        };
+        let palette_joints = scene.instance_joints(instances[1], &[1], &transforms, &skeleton);
+        assert_eq!(palette_joints, [Mat4::IDENTITY]);
+        let posed = scene.instance_bounds(instances[1], &bounds(0), &transforms, &skeleton);
+        assert_eq!(posed.min, Vec3::Z);
+        let posed = scene.instance_bounds(instances[2], &bounds(0), &transforms, &skeleton);
+        assert_eq!(posed.min, Vec3::Y);
+    }
+}
@@ -0,0 +1,215 @@
+use std::fmt;
+
+use crate::Skeleton;
+
+/// A problem with how the joints of a [`Skeleton`] are linked together
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SkeletonError {
+    /// A joint refers to a parent or child index beyond the end of the skeleton
+    OutOfBounds { joint: usize, index: usize },
+    /// A joint's parent doesn't list it as a child, or a joint lists a child whose parent is
+    /// something else
+    Orphan { joint: usize },
+    /// Following the parents of a joint leads back around to itself
+    Cycle { joint: usize },
+    /// More than one joint has no parent
+    MultipleRoots { roots: Vec<usize> },
+    /// A joint comes before its parent, which [`Skeleton::apply_pose_to_joints`] can't handle
+    OutOfOrder { joint: usize, parent: usize },
+}
+
+impl fmt::Display for SkeletonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkeletonError::OutOfBounds { joint, index } => {
+                write!(
+                    f,
+                    "Joint {joint} refers to joint {index} which doesn't exist"
+                )
+            }
+            SkeletonError::Orphan { joint } => {
+                write!(
+                    f,
+                    "Joint {joint} doesn't match its parent's list of children"
+                )
+            }
+            SkeletonError::Cycle { joint } => write!(f, "Joint {joint} is its own ancestor"),
+            SkeletonError::MultipleRoots { roots } => {
+                write!(f, "Skeleton has multiple root joints {roots:?}")
+            }
+            SkeletonError::OutOfOrder { joint, parent } => {
+                write!(f, "Joint {joint} comes before its parent {parent}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SkeletonError {}
+
+impl Skeleton {
+    /// Checks that the parent and child links between joints form a single tree, with every
+    /// parent before its children. Returns every problem found.
+    pub fn validate(&self) -> Result<(), Vec<SkeletonError>> {
+        let len = self.joints.len();
+        let mut errors = Vec::new();
+
+        for (i, joint) in self.joints.iter().enumerate() {
+            let out_of_bounds = joint
+                .parent
+                .iter()
+                .chain(&joint.children)
+                .map(|index| *index as usize)
+                .find(|index| *index >= len);
+            if let Some(index) = out_of_bounds {
+                errors.push(SkeletonError::OutOfBounds { joint: i, index });
+                continue;
+            }
+
+            let listed_by_parent = joint
+                .parent
+                .is_none_or(|p| self.joints[p as usize].children.contains(&(i as u8)));
+            let children_agree = joint
+                .children
+                .iter()
+                .all(|c| self.joints[*c as usize].parent == Some(i as u8));
+            if !listed_by_parent || !children_agree {
+                errors.push(SkeletonError::Orphan { joint: i });
+            }
+
+            // A path through the parents longer than the skeleton must have gone in a loop
+            let mut ancestor = joint.parent;
+            let mut steps = 0;
+            while let Some(p) = ancestor.filter(|p| (*p as usize) < len) {
+                if p as usize == i || steps > len {
+                    errors.push(SkeletonError::Cycle { joint: i });
+                    break;
+                }
+                ancestor = self.joints[p as usize].parent;
+                steps += 1;
+            }
+
+            if let Some(parent) = joint.parent.filter(|p| *p as usize > i) {
+                errors.push(SkeletonError::OutOfOrder {
+                    joint: i,
+                    parent: parent as usize,
+                });
+            }
+        }
+
+        let roots: Vec<usize> = (0..len)
+            .filter(|i| self.joints[*i].parent.is_none())
+            .collect();
+        if roots.len() > 1 {
+            errors.push(SkeletonError::MultipleRoots { roots });
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Finds an order for the joints where every parent comes before its children, keeping the
+    /// existing order as much as possible. Each entry is the current index of the joint that
+    /// should go in that position.
+    pub fn topological_order(&self) -> Result<Vec<usize>, SkeletonError> {
+        let len = self.joints.len();
+        let mut placed = vec![false; len];
+        let mut order = Vec::with_capacity(len);
+
+        while order.len() < len {
+            let placed_before = order.len();
+
+            for (i, joint) in self.joints.iter().enumerate() {
+                if placed[i] {
+                    continue;
+                }
+
+                let parent_placed = match joint.parent {
+                    None => true,
+                    Some(p) if p as usize >= len => {
+                        return Err(SkeletonError::OutOfBounds {
+                            joint: i,
+                            index: p as usize,
+                        })
+                    }
+                    Some(p) => placed[p as usize],
+                };
+                if parent_placed {
+                    placed[i] = true;
+                    order.push(i);
+                }
+            }
+
+            // Anything left must be in a loop
+            if order.len() == placed_before {
+                let joint = placed.iter().position(|p| !p).unwrap_or(0);
+                return Err(SkeletonError::Cycle { joint });
+            }
+        }
+
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Joint;
+
+    fn skeleton(parents: &[Option<u8>]) -> Skeleton {
+        let mut joints: Vec<Joint> = parents
+            .iter()
+            .map(|parent| Joint {
+                parent: *parent,
+                ..Default::default()
+            })
+            .collect();
+        for (i, parent) in parents.iter().enumerate() {
+            if let Some(p) = parent {
+                joints[*p as usize].children.push(i as u8);
+            }
+        }
+        Skeleton { joints }
+    }
+
+    #[test]
+    fn detects_problems() {
+        assert_eq!(skeleton(&[None, Some(0), Some(1)]).validate(), Ok(()));
+
+        let errors = skeleton(&[Some(2), None, Some(1)]).validate().unwrap_err();
+        assert!(errors.contains(&SkeletonError::OutOfOrder {
+            joint: 0,
+            parent: 2
+        }));
+
+        let errors = skeleton(&[None, Some(2), Some(1)]).validate().unwrap_err();
+        assert!(errors.contains(&SkeletonError::Cycle { joint: 1 }));
+
+        let errors = skeleton(&[None, None]).validate().unwrap_err();
+        assert_eq!(
+            errors,
+            vec![SkeletonError::MultipleRoots { roots: vec![0, 1] }]
+        );
+
+        let mut orphaned = skeleton(&[None, Some(0)]);
+        orphaned.joints[0].children.clear();
+        assert_eq!(
+            orphaned.validate(),
+            Err(vec![SkeletonError::Orphan { joint: 1 }])
+        );
+    }
+
+    #[test]
+    fn orders_parents_first() {
+        let shuffled = skeleton(&[Some(2), Some(0), None, Some(2)]);
+        assert_eq!(shuffled.topological_order(), Ok(vec![2, 3, 0, 1]));
+
+        let cyclic = skeleton(&[Some(1), Some(0)]);
+        assert!(matches!(
+            cyclic.topological_order(),
+            Err(SkeletonError::Cycle { .. })
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+//! Merging duplicate vertices, which glTF exports are full of since every vertex is split
+//! wherever any of its attributes are, and often where none of them are.
+
+use std::collections::HashMap;
+
+use glam::{IVec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::{Mesh, Vertex};
+
+/// How far apart each attribute of two vertices can be for them to be merged. Joints always have
+/// to match exactly.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WeldEpsilon {
+    /// Distance between positions, also used for morph target position offsets
+    pub pos: f32,
+    /// Distance between normals, also used for morph target normal offsets
+    pub norm: f32,
+    /// Distance between texture coordinates
+    pub tc: f32,
+    /// Difference between each weight
+    pub weight: f32,
+}
+
+impl Default for WeldEpsilon {
+    /// Only merges vertices that are the same apart from rounding errors
+    fn default() -> Self {
+        WeldEpsilon {
+            pos: 1e-5,
+            norm: 1e-3,
+            tc: 1e-5,
+            weight: 1e-3,
+        }
+    }
+}
+
+impl WeldEpsilon {
+    fn matches(&self, mesh: &Mesh, a: usize, b: usize) -> bool {
+        let (va, vb): (&Vertex, &Vertex) = (&mesh.verts[a], &mesh.verts[b]);
+        let close = |x: Option<&Vec3>, y: Option<&Vec3>, epsilon: f32| match (x, y) {
+            (Some(x), Some(y)) => x.distance(*y) <= epsilon,
+            _ => true,
+        };
+        va.pos.distance(vb.pos) <= self.pos
+            && va.norm.distance(vb.norm) <= self.norm
+            && va.tc.distance(vb.tc) <= self.tc
+            && (va.weights - vb.weights).abs().max_element() <= self.weight
+            && (0..3).all(|i| {
+                va.joints[i] == vb.joints[i] || (va.weights[i] <= 0.0 && vb.weights[i] <= 0.0)
+            })
+            && mesh.morph_targets.iter().all(|t| {
+                close(t.positions.get(a), t.positions.get(b), self.pos)
+                    && close(t.normals.get(a), t.normals.get(b), self.norm)
+            })
+    }
+}
+
+/// Merges vertices of `mesh` that are within `epsilon` of each other, keeping the first one used
+/// by the indices, and drops vertices no index uses. Triangles of a triangle list that are left
+/// with the same vertex twice are removed. Returns how many vertices were removed.
+///
+/// Meshes without indices are left alone, as every vertex would count as unused.
+pub fn weld(mesh: &mut Mesh, epsilon: &WeldEpsilon) -> usize {
+    if mesh.inds.is_empty() {
+        return 0;
+    }
+    // Positions are bucketed into cells at least as big as the position epsilon, so matches are
+    // always in the same cell or a neighbouring one
+    let cell_size = epsilon.pos.max(1e-6) * 2.0;
+    let cell = |p: Vec3| (p / cell_size).floor().as_ivec3();
+    let mut cells: HashMap<IVec3, Vec<usize>> = HashMap::new();
+
+    // Map from old vertex index to new, and new vertex index to the old one it came from
+    let mut new_index: Vec<Option<u16>> = vec![None; mesh.verts.len()];
+    let mut kept: Vec<usize> = Vec::new();
+    for i in mesh.inds.iter().map(|i| *i as usize) {
+        if new_index[i].is_some() {
+            continue;
+        }
+        let home = cell(mesh.verts[i].pos);
+        let existing = (-1..=1)
+            .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z))))
+            .filter_map(|offset| cells.get(&(home + offset)))
+            .flatten()
+            .find(|k| epsilon.matches(mesh, kept[**k], i));
+        new_index[i] = Some(match existing {
+            Some(k) => *k as u16,
+            None => {
+                kept.push(i);
+                cells.entry(home).or_default().push(kept.len() - 1);
+                (kept.len() - 1) as u16
+            }
+        });
+    }
+
+    for i in &mut mesh.inds {
+        *i = new_index[*i as usize].expect("Every used vertex has a new index");
+    }
+    if mesh.topology == crate::optimize::Topology::Triangles {
+        mesh.inds = mesh
+            .inds
+            .chunks_exact(3)
+            .filter(|t| t[0] != t[1] && t[1] != t[2] && t[0] != t[2])
+            .flatten()
+            .copied()
+            .collect();
+    }
+
+    // Vertices only used by the removed triangles are dropped too
+    let mut compacted: Vec<Option<u16>> = vec![None; kept.len()];
+    let mut used: Vec<usize> = Vec::with_capacity(kept.len());
+    for i in &mut mesh.inds {
+        *i = *compacted[*i as usize].get_or_insert_with(|| {
+            used.push(kept[*i as usize]);
+            (used.len() - 1) as u16
+        });
+    }
+
+    let removed = mesh.verts.len() - used.len();
+    mesh.verts = used.iter().map(|i| mesh.verts[*i]).collect();
+    for target in &mut mesh.morph_targets {
+        for values in [&mut target.positions, &mut target.normals] {
+            if !values.is_empty() {
+                *values = used.iter().map(|i| values[*i]).collect();
+            }
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::*;
+
+    #[test]
+    fn merges_duplicates_and_drops_unused() {
+        let vertex = |x: f32, y: f32, u: f32| Vertex {
+            pos: Vec3::new(x, y, 0.0),
+            norm: Vec3::Z,
+            tc: Vec2::new(u, 0.0),
+            ..Default::default()
+        };
+        let mut mesh = Mesh {
+            verts: vec![
+                vertex(0.0, 0.0, 0.0),
+                vertex(1.0, 0.0, 0.0),
+                vertex(0.0, 1.0, 0.0),
+                // The same as 1 and 2, once a rounding error is ignored
+                vertex(1.0, 1e-7, 0.0),
+                vertex(0.0, 1.0, 0.0),
+                vertex(1.0, 1.0, 0.0),
+                // A seam in the texture coordinates, which has to stay
+                vertex(1.0, 1.0, 0.5),
+                // Not used by any triangle
+                vertex(5.0, 5.0, 0.0),
+            ],
+            inds: vec![0, 1, 2, 3, 5, 4, 3, 6, 4],
+            ..Default::default()
+        };
+
+        assert_eq!(weld(&mut mesh, &WeldEpsilon::default()), 3);
+        assert_eq!(mesh.verts.len(), 5);
+        assert_eq!(mesh.inds, [0, 1, 2, 1, 3, 2, 1, 4, 2]);
+        assert_eq!(mesh.verts[4].tc, Vec2::new(0.5, 0.0));
+
+        // Welding everything within 2 units together leaves nothing but degenerate triangles
+        let loose = WeldEpsilon {
+            pos: 2.0,
+            tc: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(weld(&mut mesh, &loose), 5);
+        assert!(mesh.inds.is_empty());
+        assert!(mesh.verts.is_empty());
+    }
+}
@@ -1,2 +1,4 @@
+pub mod markers;
 pub mod model;
+pub mod settings;
 pub mod texture;
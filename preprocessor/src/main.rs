@@ -1,4 +1,6 @@
+pub mod markers;
 pub mod model;
+pub mod settings;
 pub mod texture;
 
 pub fn main() {
@@ -6,5 +8,6 @@ pub fn main() {
 
     let src_path = args.get(1).expect("Needs a source file");
 
-    let _ = model::load_gltf(src_path);
+    let settings = settings::ImportSettings::load_for(src_path);
+    let _ = model::load_gltf(src_path, &settings);
 }
@@ -0,0 +1,70 @@
+use std::{collections::HashMap, path::Path};
+
+use core3d::Marker;
+use serde_json::Value;
+
+/// Parses markers from a JSON object mapping marker names to a time or list of times in seconds,
+/// e.g. `{"footstep_left": 0.25, "footstep_right": 0.75, "hit": [0.4, 1.2]}`.
+/// Anything that doesn't fit that shape is skipped with a warning.
+pub fn parse_markers(value: &Value) -> Vec<Marker> {
+    let mut markers = Vec::new();
+
+    let Some(object) = value.as_object() else {
+        println!("Markers should be an object of names to times, skipping");
+        return markers;
+    };
+
+    for (name, times) in object {
+        let times = match times {
+            Value::Array(times) => times.iter().collect(),
+            time => vec![time],
+        };
+
+        for time in times {
+            match time.as_f64() {
+                Some(time) => markers.push(Marker {
+                    name: name.clone(),
+                    time: time as f32,
+                }),
+                None => println!("Marker \"{name}\" has an invalid time {time}, skipping"),
+            }
+        }
+    }
+
+    markers
+}
+
+/// Reads the `markers` object from a glTF animation's extras, which Blender exports from the
+/// action's custom properties when "Include -> Custom Properties" is enabled
+pub fn markers_from_extras(extras: &gltf::json::Extras) -> Vec<Marker> {
+    let Some(raw) = extras else {
+        return Vec::new();
+    };
+
+    match serde_json::from_str::<Value>(raw.get()) {
+        Ok(extras) => extras.get("markers").map(parse_markers).unwrap_or_default(),
+        Err(e) => {
+            println!("Couldn't parse animation extras: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Reads markers for each animation from a `<model>.markers.json` file next to the model, if one
+/// exists. The file maps animation names to markers in the form accepted by [`parse_markers`],
+/// e.g. `{"Walk": {"footstep_left": 0.25, "footstep_right": 0.75}}`.
+pub fn load_sidecar<P: AsRef<Path>>(model_file: P) -> HashMap<String, Vec<Marker>> {
+    let path = model_file.as_ref().with_extension("markers.json");
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        return HashMap::new();
+    };
+
+    println!("Reading animation markers from {}", path.display());
+    let animations: HashMap<String, Value> =
+        serde_json::from_str(&contents).expect("Couldn't parse animation markers file");
+
+    animations
+        .into_iter()
+        .map(|(name, markers)| (name, parse_markers(&markers)))
+        .collect()
+}
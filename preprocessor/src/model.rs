@@ -1,22 +1,45 @@
-use std::path::Path;
+use std::{
+    collections::{HashMap, HashSet},
+    mem::size_of,
+    path::Path,
+};
 
-use core3d::*;
-use glam::{Mat4, Quat, Vec2, Vec3};
-use gltf::animation::{util::ReadOutputs, Property};
+use core3d::{
+    bounds::MeshBounds,
+    generate::{generate_normals, project_uvs, UvProjection},
+    material::{AlphaMode, Material},
+    morph::{MorphAnimation, MorphTarget},
+    optimize::{
+        acmr, optimize_vertex_cache, optimize_vertex_fetch, to_strip, Topology, VERTEX_CACHE_SIZE,
+    },
+    packed::{self, VertexFormat},
+    partition::{partition, split_indices, MAX_INDEXED_VERTICES, MAX_PALETTE_JOINTS},
+    retarget::Retargeter,
+    scene::{Node, Scene},
+    validation::SkeletonError,
+    weld::weld,
+    *,
+};
+use glam::{Mat3, Mat4, Quat, Vec2, Vec3};
+use gltf::animation::util::ReadOutputs;
 
-use crate::texture::{swizzle, IDX_A, IDX_B, IDX_G, IDX_R};
-
-const MAX_JOINTS: u16 = 19;
+use crate::{
+    markers,
+    settings::{ImportSettings, RetargetSource},
+    texture::{swizzle, IDX_A, IDX_B, IDX_G, IDX_R},
+};
 
 #[allow(clippy::too_many_lines)]
-pub fn load_gltf<P: AsRef<Path>>(file: P) -> Model {
-    let (gltf, buffers, images) = gltf::import(file).expect("Couldn't import gltf file");
+pub fn load_gltf<P: AsRef<Path>>(file: P, settings: &ImportSettings) -> Model {
+    let (gltf, buffers, images) = gltf::import(&file).expect("Couldn't import gltf file");
 
     let mut model = Model {
         meshes: Vec::new(),
         textures: Vec::new(),
+        materials: Vec::new(),
         skeleton: Skeleton { joints: Vec::new() },
         animations: Vec::new(),
+        scene: Scene::default(),
     };
 
     // Textures
@@ -33,120 +56,80 @@ pub fn load_gltf<P: AsRef<Path>>(file: P) -> Model {
             texture.data.len()
         );
 
-        // Rearrange the texture into the correct layout
+        // Rearrange the texture into the correct layout, keeping alpha if the image has it
+        let channels = match data.format {
+            gltf::image::Format::R8G8B8A8 => 4,
+            _ => 3,
+        };
         for x in 0..data.width {
             for y in 0..data.height {
-                let src_idx = ((data.width - y - 1) * data.width + x) as usize;
+                let src_idx = ((data.width - y - 1) * data.width + x) as usize * channels;
                 let dst_idx = swizzle(x, y, data.width);
 
-                texture.data[dst_idx * 4 + IDX_R] = data.pixels[src_idx * 3 + 0];
-                texture.data[dst_idx * 4 + IDX_G] = data.pixels[src_idx * 3 + 1];
-                texture.data[dst_idx * 4 + IDX_B] = data.pixels[src_idx * 3 + 2];
-                texture.data[dst_idx * 4 + IDX_A] = 255;
+                texture.data[dst_idx * 4 + IDX_R] = data.pixels[src_idx];
+                texture.data[dst_idx * 4 + IDX_G] = data.pixels[src_idx + 1];
+                texture.data[dst_idx * 4 + IDX_B] = data.pixels[src_idx + 2];
+                texture.data[dst_idx * 4 + IDX_A] = if channels == 4 {
+                    data.pixels[src_idx + 3]
+                } else {
+                    255
+                };
             }
         }
 
         model.textures.push(texture);
     }
 
-    // Mesh
-    let gltf_mesh = gltf.meshes().next().expect("GLTF did not contain a mesh");
-
-    for primitive in gltf_mesh.primitives() {
-        println!("Extracting primitive");
-        let mut mesh = Mesh {
-            verts: Vec::new(),
-            inds: Vec::new(),
-            texture: 0,
-        };
-
-        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-
-        // Material/Texture
-        let tex_index = primitive
-            .material()
-            .pbr_metallic_roughness()
-            .base_color_texture()
-            .map(|t| t.texture().source().index() as u8);
-        if tex_index.is_none() {
-            println!("Primitive is missing a texture");
-        }
-        mesh.texture = tex_index.unwrap_or(0);
-
-        // Positions
-        for pos in reader.read_positions().expect("No vertex positions") {
-            mesh.verts.push(Vertex {
-                pos: Vec3::new(pos[0], pos[1], pos[2]),
-                ..Default::default()
-            });
-        }
-
-        // Normals
-        if let Some(norms) = reader.read_normals() {
-            for (i, norm) in norms.enumerate() {
-                mesh.verts[i].norm = Vec3::new(norm[0], norm[1], norm[2]);
-            }
-        } else {
-            println!("No normals found, defaulting to [0, 0, 0]");
-        }
-
-        // Tex coords
-        if let Some(tcs) = reader.read_tex_coords(0) {
-            for (i, tc) in tcs.into_f32().enumerate() {
-                mesh.verts[i].tc = Vec2::new(tc[0], tc[1]);
-            }
-        } else {
-            println!("No tex coords found, defaulting to [0, 0]");
-        }
-
-        // Joints
-        if let Some(joints) = reader.read_joints(0) {
-            for (i, joint_ids) in joints.into_u16().enumerate() {
-                assert!(
-                    joint_ids[0] < MAX_JOINTS
-                        && joint_ids[1] < MAX_JOINTS
-                        && joint_ids[2] < MAX_JOINTS
-                );
-                mesh.verts[i].joints = [joint_ids[0] as u8, joint_ids[1] as u8, joint_ids[2] as u8];
-            }
-        } else {
-            println!("No joints found, defaulting to [0, 0, 0]");
-        }
-
-        // Weights
-        if let Some(weights) = reader.read_weights(0) {
-            for (i, weights) in weights.into_f32().enumerate() {
-                mesh.verts[i].weights = Vec3::new(weights[0], weights[1], weights[2]).normalize();
-            }
-        } else {
-            println!("No joint weights found, defaulting to [1, 0, 0]");
-        }
-
-        // Indices
-        if let Some(indices) = reader.read_indices() {
-            for idx in indices.into_u32() {
-                mesh.inds.push(
-                    idx.try_into()
-                        .expect("Index can't fit in u16, probably too many vertices."),
-                );
-            }
-        }
-
-        model.meshes.push(mesh);
+    // Materials
+    for material in gltf.materials() {
+        let pbr = material.pbr_metallic_roughness();
+        model.materials.push(Material {
+            name: material.name().unwrap_or_default().to_string(),
+            texture: pbr
+                .base_color_texture()
+                .map(|t| t.texture().source().index() as u8),
+            base_color: pbr.base_color_factor().into(),
+            emissive: material.emissive_factor().into(),
+            alpha_mode: match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+            },
+            alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+            double_sided: material.double_sided(),
+        });
     }
 
     // Skeleton
+    // Map from glTF node index to joint index
+    let mut index_map: Vec<Option<u8>> = vec![None; gltf.nodes().len()];
     if let Some(skin) = gltf.skins().next() {
         println!("Extracting skeleton");
 
-        for joint in skin.joints() {
+        let joints: Vec<_> = skin.joints().collect();
+        assert!(joints.len() <= 255, "Skeleton has more than 255 joints");
+        for (i, joint) in joints.iter().enumerate() {
+            index_map[joint.index()] = Some(i as u8);
+        }
+
+        for joint in joints {
             let (pos, rot, scale) = joint.transform().decomposed();
             let pos = Vec3::new(pos[0], pos[1], pos[2]);
             let rot = Quat::from_xyzw(rot[0], rot[1], rot[2], rot[3]);
             let scale = Vec3::new(scale[0], scale[1], scale[2]);
             let children = joint
                 .children()
-                .map(|c| c.index().try_into().expect("Child joint out of bounds"))
+                .filter_map(|c| {
+                    let child = index_map[c.index()];
+                    if child.is_none() {
+                        println!(
+                            "Joint {:?} has a child {:?} that isn't a joint, ignoring it",
+                            joint.name(),
+                            c.name()
+                        );
+                    }
+                    child
+                })
                 .collect();
             model.skeleton.joints.push(Joint {
                 parent: None,
@@ -172,20 +155,6 @@ pub fn load_gltf<P: AsRef<Path>>(file: P) -> Model {
         model.skeleton.joints.push(Joint::default());
     }
 
-    // Remap children
-    let mut index_map = vec![0; model.skeleton.joints.len()];
-    for (i, j) in model.skeleton.joints.iter().enumerate() {
-        index_map[j.index as usize] = i as u8;
-    }
-    for i in model
-        .skeleton
-        .joints
-        .iter_mut()
-        .flat_map(|j| &mut j.children)
-    {
-        *i = index_map[*i as usize];
-    }
-
     // Parents
     let mut parents: Vec<Option<u8>> = vec![None; model.skeleton.joints.len()];
     for (i, j) in model.skeleton.joints.iter().enumerate() {
@@ -198,59 +167,956 @@ pub fn load_gltf<P: AsRef<Path>>(file: P) -> Model {
         j.parent = parents[i];
     }
 
+    // Scene, walking the default one or if there isn't one, every node that isn't a child
+    let roots: Vec<gltf::Node> = match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+        Some(scene) => scene.nodes().collect(),
+        None => {
+            let children: HashSet<usize> = gltf
+                .nodes()
+                .flat_map(|n| n.children().map(|c| c.index()))
+                .collect();
+            gltf.nodes()
+                .filter(|n| !children.contains(&n.index()))
+                .collect()
+        }
+    };
+    let mut scene_import = SceneImport {
+        buffers: &buffers,
+        settings,
+        index_map: &index_map,
+        skin: gltf.skins().next().map(|s| s.index()),
+        loaded: HashMap::new(),
+        node_map: vec![None; gltf.nodes().len()],
+    };
+    for node in roots {
+        scene_import.visit(&mut model, &node, None, None);
+    }
+    if model.meshes.is_empty() {
+        println!("GLTF did not contain a mesh");
+    }
+
     // Animations
+    let mut sidecar_markers = markers::load_sidecar(&file);
     for animation in gltf.animations() {
         let channels = animation.channels();
+        let name = animation.name().map_or(String::new(), String::from);
+
+        // Markers
+        let mut markers = markers::markers_from_extras(animation.extras());
+        markers.extend(sidecar_markers.remove(&name).unwrap_or_default());
+        markers.sort_by(|a, b| a.time.total_cmp(&b.time));
+        if !markers.is_empty() {
+            println!("Animation \"{name}\" has {} markers", markers.len());
+        }
+
         let mut animation = Animation {
-            name: animation.name().map_or(String::new(), String::from),
+            name,
             joints: vec![JointAnimation::default(); model.skeleton.joints.len()],
+            additive: false,
+            markers,
+            root_motion: None,
+            morph_weights: Vec::new(),
         };
 
-        'channels: for c in channels {
-            let bone_index = index_map[c.target().node().index()] as usize;
+        for c in channels {
             let reader = c.reader(|buffer| Some(&buffers[buffer.index()]));
+            let interpolation = match c.sampler().interpolation() {
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+            };
+            let times: Vec<f32> = reader.read_inputs().expect("No inputs?").collect();
+            let outputs = reader.read_outputs().expect("No outputs?");
 
-            for i in reader.read_inputs().expect("No inputs?") {
-                match c.target().property() {
-                    Property::Translation => animation.joints[bone_index]
-                        .translations
-                        .push((i, Vec3::ZERO)),
-                    Property::Rotation => animation.joints[bone_index]
-                        .rotations
-                        .push((i, Quat::IDENTITY)),
-                    Property::Scale => animation.joints[bone_index].scales.push((i, Vec3::ONE)),
-                    Property::MorphTargetWeights => {
-                        println!("Don't support Morph Targets");
-                        continue 'channels;
-                    }
-                }
+            // Morph target weights are animated on the node with the mesh, not on a joint
+            if let ReadOutputs::MorphTargetWeights(weights) = outputs {
+                let Some(node) = scene_import.node_map[c.target().node().index()] else {
+                    println!(
+                        "Animation channel targets node {:?} which isn't in the scene, skipping",
+                        c.target().node().name()
+                    );
+                    continue;
+                };
+                let weights: Vec<f32> = weights.into_f32().collect();
+                animation.morph_weights.push(MorphAnimation {
+                    node,
+                    weights: build_weight_tracks(&times, &weights, interpolation),
+                });
+                continue;
             }
 
-            match reader.read_outputs().expect("No outputs?") {
+            let Some(bone_index) = index_map[c.target().node().index()] else {
+                println!(
+                    "Animation channel targets node {:?} which isn't a joint, skipping",
+                    c.target().node().name()
+                );
+                continue;
+            };
+            let joint = &mut animation.joints[bone_index as usize];
+
+            match outputs {
                 ReadOutputs::Translations(o) => {
-                    for (i, t) in o.enumerate() {
-                        animation.joints[bone_index].translations[i].1 = Vec3::from_array(t);
-                    }
+                    joint.translations =
+                        build_track(&times, o.map(Vec3::from_array).collect(), interpolation);
                 }
                 ReadOutputs::Rotations(o) => {
-                    for (i, t) in o.into_f32().enumerate() {
-                        animation.joints[bone_index].rotations[i].1 = Quat::from_array(t);
-                    }
+                    joint.rotations = build_track(
+                        &times,
+                        o.into_f32().map(Quat::from_array).collect(),
+                        interpolation,
+                    );
                 }
                 ReadOutputs::Scales(o) => {
-                    for (i, t) in o.enumerate() {
-                        animation.joints[bone_index].scales[i].1 = Vec3::from_array(t);
-                    }
-                }
-                ReadOutputs::MorphTargetWeights(_) => {
-                    println!("Don't support morph targets, but also wtf this shouldn't have made it here.");
-                    continue 'channels;
+                    joint.scales =
+                        build_track(&times, o.map(Vec3::from_array).collect(), interpolation);
                 }
+                ReadOutputs::MorphTargetWeights(_) => unreachable!("Handled above"),
             }
         }
 
         model.animations.push(animation);
     }
 
+    for name in sidecar_markers.keys() {
+        println!("Markers file has markers for unknown animation \"{name}\"");
+    }
+
+    order_joints(&mut model, settings);
+
+    for source in &settings.retarget {
+        bake_retargeted(&mut model, file.as_ref(), source, settings);
+    }
+
+    weld_meshes(&mut model, settings);
+    partition_meshes(&mut model);
+
+    // Only once nothing else will move the vertices
+    for mesh in &mut model.meshes {
+        mesh.bounds = MeshBounds::from_mesh(mesh);
+    }
+
+    optimize_meshes(&mut model, settings);
+
+    for (i, mesh) in model.meshes.iter().enumerate() {
+        if mesh.format == VertexFormat::Packed {
+            report_quantization(i, mesh);
+        }
+    }
+
     model
 }
+
+/// Puts the skeleton's joints in an order it can be posed in, then takes the root motion out of
+/// the animations if `settings` asks for it, now that a root is the first joint
+fn order_joints(model: &mut Model, settings: &ImportSettings) {
+    // Posing the skeleton needs every parent to come before its children, which glTF doesn't
+    // guarantee
+    let order = model
+        .skeleton
+        .topological_order()
+        .unwrap_or_else(|e| panic!("Invalid skeleton: {e}"));
+    if order.iter().enumerate().any(|(i, j)| i != *j) {
+        println!("Reordering joints so parents come before their children");
+        reorder_joints(model, &order);
+    }
+
+    if let Err(errors) = model.skeleton.validate() {
+        for e in &errors {
+            println!("Skeleton problem: {e}");
+        }
+        assert!(
+            errors
+                .iter()
+                .all(|e| matches!(e, SkeletonError::MultipleRoots { .. })),
+            "Skeleton can't be used"
+        );
+    }
+
+    if settings.root_motion {
+        for animation in &mut model.animations {
+            println!("Extracting root motion from \"{}\"", animation.name);
+            animation.extract_root_motion(0);
+        }
+    }
+}
+
+/// Merges duplicate vertices in each mesh, printing how much space it saves
+fn weld_meshes(model: &mut Model, settings: &ImportSettings) {
+    let mut total = 0;
+    for (i, mesh) in model.meshes.iter_mut().enumerate() {
+        let removed = weld(mesh, &settings.weld);
+        if removed == 0 {
+            continue;
+        }
+        // Each morph target has an offset for every vertex too
+        let morph_size: usize = mesh
+            .morph_targets
+            .iter()
+            .map(|t| (t.positions.len().min(1) + t.normals.len().min(1)) * size_of::<Vec3>())
+            .sum();
+        let saved = removed * (mesh.format.stride() + morph_size);
+        println!("Welded mesh {i}, removed {removed} vertices and saved {saved} bytes");
+        total += saved;
+    }
+    if total > 0 {
+        println!("Welding saved {total} bytes in total");
+    }
+}
+
+/// Splits meshes that use more joints than the shader has room for, see [`partition()`]
+fn partition_meshes(model: &mut Model) {
+    // Map from old mesh index to the indices of its parts
+    let mut new_indices = Vec::with_capacity(model.meshes.len());
+    let mut meshes = Vec::with_capacity(model.meshes.len());
+    for (i, mesh) in model.meshes.iter().enumerate() {
+        let parts = partition(mesh, MAX_PALETTE_JOINTS);
+        if parts.len() > 1 || !parts[0].palette.is_empty() {
+            println!(
+                "Splitting mesh {i} into {} parts to fit the joint palette",
+                parts.len()
+            );
+        }
+        new_indices.push((meshes.len()..meshes.len() + parts.len()).collect::<Vec<_>>());
+        meshes.extend(parts);
+    }
+    model.meshes = meshes;
+
+    for node in &mut model.scene.nodes {
+        node.meshes = node
+            .meshes
+            .iter()
+            .flat_map(|m| new_indices[*m].iter().copied())
+            .collect();
+    }
+}
+
+/// Reorders each mesh's triangles and vertices to make it faster to draw, and turns it into a
+/// triangle strip if the settings ask for it. Prints the ACMR before and after.
+fn optimize_meshes(model: &mut Model, settings: &ImportSettings) {
+    for (i, mesh) in model.meshes.iter_mut().enumerate() {
+        if mesh.topology != Topology::Triangles {
+            continue;
+        }
+        let before = acmr(&mesh.inds, mesh.topology, VERTEX_CACHE_SIZE);
+        optimize_vertex_cache(&mut mesh.inds, mesh.verts.len(), VERTEX_CACHE_SIZE);
+        optimize_vertex_fetch(mesh);
+        if settings.triangle_strips {
+            mesh.inds = to_strip(&mesh.inds);
+            mesh.topology = Topology::TriangleStrip;
+        }
+        println!(
+            "Optimized mesh {i}, ACMR {before:.3} -> {:.3}",
+            acmr(&mesh.inds, mesh.topology, VERTEX_CACHE_SIZE)
+        );
+    }
+}
+
+/// Prints how much precision is lost by packing the mesh's vertices
+fn report_quantization(index: usize, mesh: &Mesh) {
+    let (packed, dequantize) = packed::pack(&mesh.verts);
+    let error = packed::quantization_error(&mesh.verts, &packed, &dequantize);
+    println!(
+        "Packed mesh {index}, largest errors: position {:.5}, normal {:.3}°, texture coordinate {:.5}, weight {:.4}",
+        error.pos,
+        error.norm.to_degrees(),
+        error.tc,
+        error.weight
+    );
+}
+
+/// Imports the model described by `source` and adds its animations to `model`, retargeted onto
+/// `model`'s skeleton
+fn bake_retargeted(
+    model: &mut Model,
+    file: &Path,
+    source: &RetargetSource,
+    settings: &ImportSettings,
+) {
+    let path = file.parent().unwrap_or(Path::new("")).join(&source.file);
+    println!("Retargeting animations from {}", path.display());
+    let source_model = load_gltf(&path, &ImportSettings::default());
+
+    let aliases: Vec<(&str, &str)> = source
+        .aliases
+        .iter()
+        .map(|(s, t)| (s.as_str(), t.as_str()))
+        .collect();
+    let retargeter = Retargeter::new(&source_model.skeleton, &model.skeleton, &aliases);
+    for i in retargeter.unmapped() {
+        println!(
+            "No joint in {} for \"{}\", it will stay in its base pose",
+            path.display(),
+            model.skeleton.joints[i].name
+        );
+    }
+
+    for animation in &source_model.animations {
+        let mut animation = retargeter.retarget(animation);
+        if settings.root_motion {
+            println!("Extracting root motion from \"{}\"", animation.name);
+            animation.extract_root_motion(0);
+        }
+        model.animations.push(animation);
+    }
+}
+
+/// State for walking the glTF node tree and building the model's [`Scene`]
+struct SceneImport<'a> {
+    buffers: &'a [gltf::buffer::Data],
+    settings: &'a ImportSettings,
+    /// Map from glTF node index to joint index
+    index_map: &'a [Option<u8>],
+    /// The skin the skeleton was made from
+    skin: Option<usize>,
+    /// Map from glTF mesh index and whether it's skinned to the meshes loaded from it, for meshes
+    /// that are used as is
+    loaded: HashMap<(usize, bool), Vec<usize>>,
+    /// Map from glTF node index to scene node index
+    node_map: Vec<Option<usize>>,
+}
+
+impl SceneImport<'_> {
+    /// Adds `node` and everything below it to the scene. `from_joint` is the closest joint above
+    /// `node`'s parent and the transform from that joint to the parent.
+    fn visit(
+        &mut self,
+        model: &mut Model,
+        node: &gltf::Node,
+        parent: Option<usize>,
+        from_joint: Option<(u8, Mat4)>,
+    ) {
+        let (pos, rot, scale) = node.transform().decomposed();
+        let transform = JointTransform {
+            pos: Vec3::from_array(pos),
+            rot: Quat::from_array(rot),
+            scale: Vec3::from_array(scale),
+        };
+        let joint = self.index_map[node.index()];
+        let from_joint = match joint {
+            Some(joint) => Some((joint, Mat4::IDENTITY)),
+            None => from_joint.map(|(joint, m)| (joint, m * Mat4::from(transform))),
+        };
+
+        let index = model.scene.nodes.len();
+        self.node_map[node.index()] = Some(index);
+        let mut scene_node = Node {
+            name: node.name().map_or(String::new(), String::from),
+            transform,
+            joint,
+            parent,
+            ..Default::default()
+        };
+
+        if let Some(mesh) = node.mesh() {
+            if let Some(skin) = node.skin() {
+                if Some(skin.index()) != self.skin {
+                    println!(
+                        "Mesh {:?} uses a different skin to the skeleton, it won't animate properly",
+                        mesh.name()
+                    );
+                }
+                scene_node.meshes = self.load_shared(model, &mesh, true);
+                scene_node.skinned = true;
+            } else if let Some((joint, from_joint)) = from_joint {
+                println!("Attaching mesh {:?} to joint {joint}", mesh.name());
+                scene_node.meshes = self.load_rigid(model, &mesh, joint, from_joint);
+                scene_node.skinned = true;
+            } else {
+                scene_node.meshes = self.load_shared(model, &mesh, false);
+            }
+        }
+
+        model.scene.nodes.push(scene_node);
+        if let Some(parent) = parent {
+            model.scene.nodes[parent].children.push(index);
+        }
+
+        for child in node.children() {
+            self.visit(model, &child, Some(index), from_joint);
+        }
+    }
+
+    /// Loads `mesh` as is, or reuses it if another node already loaded it the same way
+    fn load_shared(&mut self, model: &mut Model, mesh: &gltf::Mesh, skinned: bool) -> Vec<usize> {
+        if let Some(meshes) = self.loaded.get(&(mesh.index(), skinned)) {
+            return meshes.clone();
+        }
+
+        let meshes = self.load(model, mesh);
+        if !skinned {
+            // Bound entirely to the first joint, so it can be drawn with the node's transform in
+            // that joint's place, see `Scene::instance_joints`
+            for m in &meshes {
+                for vert in &mut model.meshes[*m].verts {
+                    vert.joints = [0; 3];
+                    vert.weights = Vec3::X;
+                }
+            }
+        }
+        self.loaded.insert((mesh.index(), skinned), meshes.clone());
+        meshes
+    }
+
+    /// Loads a mesh that isn't skinned but is below a joint in the scene, moving its vertices
+    /// into the skeleton's bind pose and binding them all to that joint so it's skinned like the
+    /// rest of the model
+    fn load_rigid(
+        &mut self,
+        model: &mut Model,
+        mesh: &gltf::Mesh,
+        joint: u8,
+        from_joint: Mat4,
+    ) -> Vec<usize> {
+        let bind = model.skeleton.joints[joint as usize]
+            .inverse_bind_matrix
+            .inverse()
+            * from_joint;
+        let normal_matrix = Mat3::from_mat4(bind).inverse().transpose();
+
+        let meshes = self.load(model, mesh);
+        for m in &meshes {
+            let mesh = &mut model.meshes[*m];
+            for vert in &mut mesh.verts {
+                vert.pos = bind.transform_point3(vert.pos);
+                vert.norm = (normal_matrix * vert.norm).normalize_or_zero();
+                vert.joints = [joint; 3];
+                vert.weights = Vec3::X;
+            }
+            for target in &mut mesh.morph_targets {
+                for offset in &mut target.positions {
+                    *offset = bind.transform_vector3(*offset);
+                }
+                for offset in &mut target.normals {
+                    *offset = normal_matrix * *offset;
+                }
+            }
+        }
+        meshes
+    }
+
+    fn load(&self, model: &mut Model, mesh: &gltf::Mesh) -> Vec<usize> {
+        let target_names = morph_target_names(mesh);
+        let mut meshes = Vec::new();
+        for primitive in mesh.primitives() {
+            let material = match primitive.material().index() {
+                Some(index) => index as u8,
+                None => default_material(model),
+            };
+            for mut loaded in load_primitive(&primitive, self.buffers, self.settings) {
+                loaded.material = material;
+                for (target, name) in loaded.morph_targets.iter_mut().zip(&target_names) {
+                    target.name.clone_from(name);
+                }
+                loaded.morph_weights = mesh
+                    .weights()
+                    .map_or_else(|| vec![0.0; loaded.morph_targets.len()], <[f32]>::to_vec);
+                model.meshes.push(loaded);
+                meshes.push(model.meshes.len() - 1);
+            }
+        }
+        meshes
+    }
+}
+
+/// Moves the joints of the model's skeleton into `order`, where each entry is the current index
+/// of the joint that should go in that position, and updates everything that refers to joints
+fn reorder_joints(model: &mut Model, order: &[usize]) {
+    let mut new_index = vec![0; order.len()];
+    for (new, old) in order.iter().enumerate() {
+        new_index[*old] = new as u8;
+    }
+    let remap = |i: u8| new_index[i as usize];
+
+    // Skeleton, the inverse bind matrices are stored on each joint so they move with it
+    let mut old_joints: Vec<Option<Joint>> = model.skeleton.joints.drain(..).map(Some).collect();
+    for old in order {
+        let mut joint = old_joints[*old]
+            .take()
+            .expect("Joint used twice in new order");
+        joint.parent = joint.parent.map(remap);
+        for c in &mut joint.children {
+            *c = remap(*c);
+        }
+        model.skeleton.joints.push(joint);
+    }
+
+    // Vertices, only of skinned meshes, as the others are drawn with their node's transform in
+    // place of joint 0 whichever joint ends up there
+    let skinned: HashSet<usize> = model
+        .scene
+        .nodes
+        .iter()
+        .filter(|n| n.skinned)
+        .flat_map(|n| n.meshes.iter().copied())
+        .collect();
+    for (_, mesh) in model
+        .meshes
+        .iter_mut()
+        .enumerate()
+        .filter(|(i, _)| skinned.contains(i))
+    {
+        for vert in &mut mesh.verts {
+            vert.joints = vert.joints.map(remap);
+        }
+    }
+
+    // Scene
+    for node in &mut model.scene.nodes {
+        node.joint = node.joint.map(remap);
+    }
+
+    // Animation tracks
+    for animation in &mut model.animations {
+        let mut old_tracks: Vec<_> = std::mem::take(&mut animation.joints);
+        animation.joints = order
+            .iter()
+            .map(|old| std::mem::take(&mut old_tracks[*old]))
+            .collect();
+    }
+}
+
+/// Names of a mesh's morph targets, which glTF doesn't have a place for but Blender puts in the
+/// mesh's extras
+fn morph_target_names(mesh: &gltf::Mesh) -> Vec<String> {
+    let Some(raw) = mesh.extras() else {
+        return Vec::new();
+    };
+    serde_json::from_str::<serde_json::Value>(raw.get())
+        .ok()
+        .and_then(|extras| {
+            let names = extras.get("targetNames")?.as_array()?;
+            Some(
+                names
+                    .iter()
+                    .map(|n| n.as_str().unwrap_or_default().to_string())
+                    .collect(),
+            )
+        })
+        .unwrap_or_default()
+}
+
+/// Index of the material used by primitives without one, adding it to the model if it isn't
+/// there yet
+fn default_material(model: &mut Model) -> u8 {
+    let default = Material::default();
+    if let Some(index) = model.materials.iter().position(|m| *m == default) {
+        return index as u8;
+    }
+    println!("Primitive is missing a material, using a plain white one");
+    model.materials.push(default);
+    (model.materials.len() - 1) as u8
+}
+
+/// Reads the vertices and indices of one part of a glTF mesh, split into more than one mesh if it
+/// has too many vertices for 16 bit indices. Their material is set by the caller.
+fn load_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+    settings: &ImportSettings,
+) -> Vec<Mesh> {
+    println!("Extracting primitive");
+    let mut mesh = Mesh {
+        verts: Vec::new(),
+        inds: Vec::new(),
+        material: 0,
+        format: settings.vertex_format,
+        bounds: MeshBounds::default(),
+        morph_targets: Vec::new(),
+        morph_weights: Vec::new(),
+        palette: Vec::new(),
+        topology: Default::default(),
+    };
+
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    // Positions
+    for pos in reader.read_positions().expect("No vertex positions") {
+        mesh.verts.push(Vertex {
+            pos: Vec3::new(pos[0], pos[1], pos[2]),
+            ..Default::default()
+        });
+    }
+
+    // Normals
+    let has_normals = if let Some(norms) = reader.read_normals() {
+        for (i, norm) in norms.enumerate() {
+            mesh.verts[i].norm = Vec3::new(norm[0], norm[1], norm[2]);
+        }
+        true
+    } else {
+        false
+    };
+
+    // Tex coords
+    let has_tex_coords = if let Some(tcs) = reader.read_tex_coords(0) {
+        for (i, tc) in tcs.into_f32().enumerate() {
+            mesh.verts[i].tc = Vec2::new(tc[0], tc[1]);
+        }
+        true
+    } else {
+        false
+    };
+
+    // Joints
+    if let Some(joints) = reader.read_joints(0) {
+        for (i, joint_ids) in joints.into_u16().enumerate() {
+            assert!(
+                joint_ids[..3].iter().all(|j| *j <= u16::from(u8::MAX)),
+                "Joint index too big, the skeleton can't have more than 255 joints"
+            );
+            mesh.verts[i].joints = [joint_ids[0] as u8, joint_ids[1] as u8, joint_ids[2] as u8];
+        }
+    } else {
+        println!("No joints found, defaulting to [0, 0, 0]");
+    }
+
+    // Weights, adding up to 1 like packed vertices' do
+    if let Some(weights) = reader.read_weights(0) {
+        for (i, weights) in weights.into_f32().enumerate() {
+            mesh.verts[i].weights =
+                packed::normalize_weights(Vec3::new(weights[0], weights[1], weights[2]));
+        }
+    } else {
+        println!("No joint weights found, defaulting to [1, 0, 0]");
+    }
+
+    // Morph targets, without tangents as there's nothing to use them
+    for (positions, normals, _) in reader.read_morph_targets() {
+        let vertex_count = mesh.verts.len();
+        mesh.morph_targets.push(MorphTarget {
+            name: String::new(),
+            positions: positions.map_or_else(
+                || vec![Vec3::ZERO; vertex_count],
+                |p| p.map(Vec3::from_array).collect(),
+            ),
+            normals: normals.map_or_else(Vec::new, |n| n.map(Vec3::from_array).collect()),
+        });
+    }
+    if !mesh.morph_targets.is_empty() {
+        println!("Primitive has {} morph targets", mesh.morph_targets.len());
+    }
+
+    // Indices, with every vertex used in order if there aren't any
+    let mut indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..mesh.verts.len() as u32).collect(),
+    };
+
+    // Making up anything missing, which can add vertices so has to come before splitting
+    if !has_normals {
+        println!("No normals found, generating them ({:?})", settings.normals);
+        generate_normals(&mut mesh, &mut indices, settings.normals);
+    }
+    if !has_tex_coords {
+        if settings.uv_projection == UvProjection::None {
+            println!("No tex coords found, defaulting to [0, 0]");
+        } else {
+            println!(
+                "No tex coords found, projecting them ({:?})",
+                settings.uv_projection
+            );
+            project_uvs(&mut mesh, &mut indices, settings.uv_projection);
+        }
+    }
+
+    split_primitive(mesh, &indices)
+}
+
+/// Gives `mesh` the triangles of `indices`, which index into its vertices, split into more than
+/// one mesh if it has too many vertices for 16 bit indices
+fn split_primitive(mut mesh: Mesh, indices: &[u32]) -> Vec<Mesh> {
+    if mesh.verts.len() <= MAX_INDEXED_VERTICES {
+        mesh.inds = indices.iter().map(|i| *i as u16).collect();
+        return vec![mesh];
+    }
+
+    let chunks = split_indices(&mesh, indices, MAX_INDEXED_VERTICES);
+    println!(
+        "Primitive has {} vertices, too many for 16 bit indices, splitting it into {} meshes",
+        mesh.verts.len(),
+        chunks.len()
+    );
+    chunks
+}
+
+/// Splits the weights of every morph target at each keyframe into a track for each target
+fn build_weight_tracks(
+    times: &[f32],
+    weights: &[f32],
+    interpolation: Interpolation,
+) -> Vec<Track<f32>> {
+    let values_per_key = if interpolation == Interpolation::CubicSpline {
+        3
+    } else {
+        1
+    };
+    let count = weights.len() / (times.len() * values_per_key).max(1);
+    if count == 0 {
+        return Vec::new();
+    }
+    (0..count)
+        .map(|target| {
+            let values = weights.chunks_exact(count).map(|w| w[target]).collect();
+            build_track(times, values, interpolation)
+        })
+        .collect()
+}
+
+/// Builds a track from a glTF sampler's keyframe times and output values.
+/// Cubic spline samplers store an (in tangent, value, out tangent) triplet for each keyframe.
+fn build_track<T: Interpolate>(
+    times: &[f32],
+    values: Vec<T>,
+    interpolation: Interpolation,
+) -> Track<T> {
+    if interpolation != Interpolation::CubicSpline {
+        assert_eq!(times.len(), values.len(), "Mismatched animation keyframes");
+        return Track::new(interpolation, times.iter().copied().zip(values).collect());
+    }
+
+    assert_eq!(
+        times.len() * 3,
+        values.len(),
+        "Mismatched cubic spline animation keyframes"
+    );
+    let mut track = Track::new(interpolation, Vec::with_capacity(times.len()));
+    for (t, triplet) in times.iter().zip(values.chunks_exact(3)) {
+        track.keys.push((*t, triplet[1]));
+        track.tangents.push((triplet[0], triplet[2]));
+    }
+
+    track
+}
+
+#[cfg(test)]
+mod tests {
+    use core3d::container::IndexFormat;
+
+    use super::*;
+
+    /// A model with no meshes and `joints` as its skeleton
+    fn skeleton_model(joints: Vec<Joint>, animations: Vec<Animation>) -> Model {
+        Model {
+            meshes: Vec::new(),
+            textures: Vec::new(),
+            materials: Vec::new(),
+            skeleton: Skeleton { joints },
+            animations,
+            scene: Scene::default(),
+        }
+    }
+
+    fn walk(from: Vec3, to: Vec3) -> Track<Vec3> {
+        Track::new(Interpolation::Linear, vec![(0.0, from), (1.0, to)])
+    }
+
+    #[test]
+    fn root_motion_comes_from_root_after_reordering() {
+        // The skin lists the hand before the hips it's attached to
+        let joints = vec![
+            Joint {
+                name: "Hand".to_string(),
+                parent: Some(1),
+                ..Default::default()
+            },
+            Joint {
+                name: "Hips".to_string(),
+                children: vec![0],
+                ..Default::default()
+            },
+        ];
+        let animation = Animation {
+            name: "Walk".to_string(),
+            joints: vec![
+                JointAnimation {
+                    translations: walk(Vec3::X, Vec3::new(1.0, 1.0, 0.0)),
+                    ..Default::default()
+                },
+                JointAnimation {
+                    translations: walk(Vec3::Y, Vec3::new(2.0, 1.0, 0.0)),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let mut model = skeleton_model(joints, vec![animation]);
+        let settings = ImportSettings {
+            root_motion: true,
+            ..Default::default()
+        };
+        order_joints(&mut model, &settings);
+
+        let [hips, hand] = &model.skeleton.joints[..] else {
+            panic!("Expected 2 joints");
+        };
+        assert_eq!(
+            (hips.name.as_str(), hips.parent, &hips.children[..]),
+            ("Hips", None, &[1][..])
+        );
+        assert_eq!((hand.name.as_str(), hand.parent), ("Hand", Some(0)));
+
+        // The hips stay in place and their movement is the root motion, while the hand keeps its
+        // own movement
+        let animation = &model.animations[0];
+        assert_eq!(animation.joints[0].translations.sample(1.0), Some(Vec3::Y));
+        assert_eq!(
+            animation.joints[1].translations.sample(1.0),
+            Some(Vec3::new(1.0, 1.0, 0.0))
+        );
+        let motion = animation
+            .root_motion
+            .as_ref()
+            .expect("Root motion extracted");
+        assert_eq!(motion.translations.sample(1.0), Some(Vec3::X * 2.0));
+    }
+
+    #[test]
+    fn splits_primitives_too_big_for_16_bit_indices() {
+        // Triangles that don't share vertices, 150 more vertices than fit in one mesh
+        let triangles = MAX_INDEXED_VERTICES / 3 + 50;
+        let mesh = Mesh {
+            verts: (0..triangles * 3)
+                .map(|i| Vertex {
+                    pos: Vec3::new(i as f32, 0.0, 0.0),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let indices: Vec<u32> = (0..triangles as u32 * 3).collect();
+
+        let parts = split_primitive(mesh.clone(), &indices);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].verts.len(), 65535);
+        assert_eq!(parts[1].verts.len(), 150);
+        assert_eq!(
+            parts.iter().map(|p| p.inds.len()).sum::<usize>(),
+            indices.len()
+        );
+        // The small part is written with 8 bit indices
+        assert_eq!(
+            parts
+                .iter()
+                .map(|p| IndexFormat::for_vertex_count(p.verts.len()))
+                .collect::<Vec<_>>(),
+            [IndexFormat::U16, IndexFormat::U8]
+        );
+        for part in &parts {
+            assert!(part
+                .inds
+                .chunks_exact(3)
+                .all(|t| t.iter().all(|i| (*i as usize) < part.verts.len())));
+        }
+
+        // One that fits is kept whole
+        let parts = split_primitive(mesh, &indices[..300]);
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].inds[299], 299);
+    }
+
+    #[test]
+    fn reordering_joints_updates_references() {
+        // The root is last, and each joint is the parent of the one before it
+        let joints = vec![
+            Joint {
+                name: "Arm".to_string(),
+                parent: Some(2),
+                children: vec![1],
+                ..Default::default()
+            },
+            Joint {
+                name: "Hand".to_string(),
+                parent: Some(0),
+                ..Default::default()
+            },
+            Joint {
+                name: "Root".to_string(),
+                children: vec![0],
+                ..Default::default()
+            },
+        ];
+        let animation = Animation {
+            joints: (0..3)
+                .map(|i| JointAnimation {
+                    translations: walk(Vec3::splat(i as f32), Vec3::splat(i as f32)),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let mut model = skeleton_model(joints, vec![animation]);
+        let vertex = |joints: [u8; 3]| Vertex {
+            joints,
+            ..Default::default()
+        };
+        model.meshes = vec![
+            Mesh {
+                verts: vec![vertex([0, 1, 2])],
+                ..Default::default()
+            },
+            Mesh {
+                verts: vec![vertex([0, 0, 0])],
+                ..Default::default()
+            },
+        ];
+        model.scene.nodes = vec![
+            Node {
+                meshes: vec![0],
+                skinned: true,
+                ..Default::default()
+            },
+            Node {
+                meshes: vec![1],
+                ..Default::default()
+            },
+            Node {
+                joint: Some(1),
+                ..Default::default()
+            },
+        ];
+
+        let order = model.skeleton.topological_order().unwrap();
+        assert_eq!(order, [2, 0, 1]);
+        reorder_joints(&mut model, &order);
+
+        let joints = &model.skeleton.joints;
+        assert_eq!(
+            joints.iter().map(|j| j.name.as_str()).collect::<Vec<_>>(),
+            ["Root", "Arm", "Hand"]
+        );
+        assert_eq!(
+            joints.iter().map(|j| j.parent).collect::<Vec<_>>(),
+            [None, Some(0), Some(1)]
+        );
+        assert_eq!(
+            (&joints[0].children[..], &joints[1].children[..]),
+            (&[1][..], &[2][..])
+        );
+
+        // Only the skinned mesh's vertices refer to skeleton joints
+        assert_eq!(model.meshes[0].verts[0].joints, [1, 2, 0]);
+        assert_eq!(model.meshes[1].verts[0].joints, [0, 0, 0]);
+        assert_eq!(model.scene.nodes[2].joint, Some(2));
+        let tracks = &model.animations[0].joints;
+        assert_eq!(tracks[0].translations.sample(0.0), Some(Vec3::splat(2.0)));
+        assert_eq!(tracks[2].translations.sample(0.0), Some(Vec3::ONE));
+    }
+
+    #[test]
+    fn splits_cubic_spline_weights_by_target() {
+        // For each keyframe, the in tangents, values and out tangents of both targets
+        #[rustfmt::skip]
+        let weights = [
+            0.1, 0.2, 1.0, 2.0, 0.3, 0.4,
+            0.5, 0.6, 3.0, 4.0, 0.7, 0.8,
+        ];
+        let tracks = build_weight_tracks(&[0.0, 1.0], &weights, Interpolation::CubicSpline);
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[1].keys, [(0.0, 2.0), (1.0, 4.0)]);
+        assert_eq!(tracks[1].tangents, [(0.2, 0.4), (0.6, 0.8)]);
+
+        let tracks = build_weight_tracks(&[0.0, 1.0], &[1.0, 2.0, 3.0, 4.0], Interpolation::Linear);
+        assert_eq!(tracks[0].keys, [(0.0, 1.0), (1.0, 3.0)]);
+        assert!(tracks[0].tangents.is_empty());
+    }
+}
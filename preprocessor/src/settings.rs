@@ -0,0 +1,61 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use core3d::{
+    generate::{NormalMode, UvProjection},
+    packed::VertexFormat,
+    weld::WeldEpsilon,
+};
+use serde::Deserialize;
+
+/// Options for how a model is imported, read from a `<model>.import.json` file next to the model
+/// if one exists, e.g. `{"root_motion": true}`. Any options left out use their default value.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ImportSettings {
+    /// Moves the horizontal movement and turning of the root joint out of each animation and into
+    /// its root motion track, see `core3d::Animation::extract_root_motion`
+    pub root_motion: bool,
+    /// Other models whose animations are retargeted onto this model's skeleton and added to it,
+    /// see `core3d::retarget::Retargeter`
+    pub retarget: Vec<RetargetSource>,
+    /// How vertices are stored, `"packed"` for `core3d::packed::PackedVertex` which is less than
+    /// half the size but loses some precision
+    pub vertex_format: VertexFormat,
+    /// Draws meshes as triangle strips instead of lists, see `core3d::optimize::to_strip`. Strips
+    /// are usually fewer indices but don't always draw faster.
+    pub triangle_strips: bool,
+    /// How close vertices have to be to be merged, e.g. `{"pos": 0.001, "tc": 0.0001}`, see
+    /// `core3d::weld::weld`
+    pub weld: WeldEpsilon,
+    /// How normals are made for meshes exported without them, `"flat"` or e.g.
+    /// `{"smooth": {"crease_angle": 45}}` with the angle in degrees
+    pub normals: NormalMode,
+    /// How texture coordinates are made for meshes exported without them, `"planar"` or `"box"`.
+    /// By default they're all left at 0.
+    pub uv_projection: UvProjection,
+}
+
+/// A model to take animations from, e.g. `{"file": "Mannequin.glb", "aliases": {"pelvis": "Hips"}}`
+#[derive(Clone, Debug, Deserialize)]
+pub struct RetargetSource {
+    /// Path of the model, relative to the model being imported
+    pub file: PathBuf,
+    /// Joints named differently between the two skeletons, from source name to target name
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+}
+
+impl ImportSettings {
+    pub fn load_for<P: AsRef<Path>>(model_file: P) -> Self {
+        let path = model_file.as_ref().with_extension("import.json");
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+
+        println!("Reading import settings from {}", path.display());
+        serde_json::from_str(&contents).expect("Couldn't parse import settings")
+    }
+}